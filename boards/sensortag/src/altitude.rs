@@ -0,0 +1,471 @@
+//! Relative altitude and pressure trends computed from BMP280 samples.
+//!
+//! The altitude is derived with the barometric formula
+//! `h = 44330 m * (1 - (p / p0) ^ (1 / 5.255))` relative to a reference
+//! pressure `p0`, which each application sets for itself and which defaults
+//! to the standard sea level pressure. The
+//! power is evaluated in fixed point through `log2` and `exp2`, which keeps
+//! the result within about half a meter of the floating point formula.
+//!
+//! While any application tracks the trend a sample is taken at a fixed
+//! interval and kept in a rolling history. Once a sample has been added the
+//! pressure change across the history is compared against a threshold to
+//! flag whether the pressure is rising, falling or steady. The history, the
+//! interval and the threshold are shared by all applications.
+//!
+//! Commands:
+//!
+//!     * 0: driver check
+//!     * 1: read the altitude
+//!     * 2: set the reference pressure (`data`, in Pa)
+//!     * 3: take a sample and use it as the reference pressure
+//!     * 4: start tracking the pressure trend (`data`, interval in ms)
+//!     * 5: stop tracking the pressure trend
+//!     * 6: set the trend threshold (`data`, in Pa across the history, from
+//!       1 to `i32::MAX`)
+//!
+//! Events are delivered to the callback subscribed with number 0:
+//!
+//!     * `callback(1, altitude, pressure)`: the altitude in signed
+//!       centimeters and the pressure it was computed from in Pa
+//!     * `callback(2, trend, change)`: the trend (0 steady, 1 rising,
+//!       2 falling) and the signed pressure change across the history in Pa
+//!     * `callback(3, reference, 0)`: the new reference pressure in Pa
+//!
+//! If the sample for an altitude or reference request cannot be read, the
+//! event carries `sensor::READING_FAILED` in place of the altitude or the
+//! reference, which is then left unchanged. A failed trend sample only
+//! leaves a gap in the history.
+
+use cc26x0::bmp280::{self, PressureClient};
+use cc26x0::i2c::I2cError;
+use cc26x0::rtc;
+use cc26x0::sensor::{self, RegisterBus};
+use core::cell::Cell;
+use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
+use kernel::hil::time::{self, Alarm, Time};
+
+pub const DRIVER_NUM: usize = 0x90003;
+
+/// Standard sea level pressure in Pa
+pub const SEA_LEVEL_PRESSURE: u32 = 101_325;
+
+/// Number of samples kept for trend detection
+pub const HISTORY_LEN: usize = 12;
+
+pub const DEFAULT_INTERVAL_MS: u32 = 15 * 60 * 1000;
+pub const MIN_INTERVAL_MS: u32 = 1000;
+pub const MAX_INTERVAL_MS: u32 = 60 * 60 * 1000;
+
+/// A change of 1 hPa across the history is reported as a trend
+pub const DEFAULT_TREND_THRESHOLD: u32 = 100;
+
+// 1 / 5.255 in 16.16 fixed point
+const EXPONENT_Q16: i64 = 12471;
+// 44330 m in centimeters
+const SCALE_HEIGHT_CM: i64 = 4_433_000;
+
+// 2 ^ (2 ^ -k) for k = 1..16 in 2.30 fixed point
+const EXP2_FRACTIONS: [u64; 16] = [
+    1518500250, 1276901417, 1170923762, 1121280436, 1097253708, 1085434106, 1079572136,
+    1076653033, 1075196443, 1074468888, 1074105294, 1073923544, 1073832680, 1073787251,
+    1073764537, 1073753181,
+];
+
+/// Base 2 logarithm of a positive 16.16 fixed point value, in 16.16
+pub fn log2_q16(x: u32) -> i32 {
+    if x == 0 {
+        return i32::min_value();
+    }
+
+    // Normalize to [1, 2) to get the integer part
+    let msb = 31 - x.leading_zeros() as i32;
+    let mut result = (msb - 16) << 16;
+    let mut y: u64 = if msb >= 16 {
+        (x >> (msb - 16)) as u64
+    } else {
+        (x << (16 - msb)) as u64
+    };
+
+    // Each squaring yields the next fractional bit
+    for bit in (0..16).rev() {
+        y = (y * y) >> 16;
+        if y >= 2 << 16 {
+            y >>= 1;
+            result |= 1 << bit;
+        }
+    }
+    result
+}
+
+// 2 ^ fraction for a 16 bit fraction, in 2.30 fixed point
+fn exp2_fraction_q30(fraction: i32) -> u64 {
+    let mut y: u64 = 1 << 30;
+    for (i, factor) in EXP2_FRACTIONS.iter().enumerate() {
+        if fraction & (1 << (15 - i)) != 0 {
+            y = (y * factor) >> 30;
+        }
+    }
+    y
+}
+
+/// Base 2 power of a signed 16.16 fixed point value, in 16.16
+pub fn exp2_q16(x: i32) -> u32 {
+    let integer = x >> 16;
+    let y = exp2_fraction_q30(x & 0xFFFF);
+
+    // Back to 16.16 and apply the integer part
+    let shift = 14 - integer;
+    if shift >= 64 {
+        0
+    } else if shift >= 0 {
+        (y >> shift) as u32
+    } else if shift < -1 {
+        u32::max_value()
+    } else {
+        (y << -shift) as u32
+    }
+}
+
+/// Altitude in centimeters above the level where the pressure equals `reference`
+pub fn altitude_cm(pressure: u32, reference: u32) -> i32 {
+    if pressure == 0 || reference == 0 {
+        return 0;
+    }
+
+    let ratio = ((pressure as u64) << 16) / reference as u64;
+    if ratio == 0 || ratio > u32::max_value() as u64 {
+        return 0;
+    }
+
+    // The power is kept in 2.30 fixed point, 16.16 would limit the
+    // resolution to about 70 cm
+    let exponent = ((log2_q16(ratio as u32) as i64 * EXPONENT_Q16) >> 16) as i32;
+    let integer = exponent >> 16;
+    let fraction = exp2_fraction_q30(exponent & 0xFFFF) as i64;
+    let power = if integer < 0 {
+        fraction >> -integer
+    } else if integer < 32 {
+        fraction << integer
+    } else {
+        return i32::min_value();
+    };
+    ((SCALE_HEIGHT_CM * ((1 << 30) - power)) >> 30) as i32
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Trend {
+    Steady = 0,
+    Rising = 1,
+    Falling = 2,
+}
+
+/// Classifies the pressure change from `oldest` to `newest`, `threshold` is
+/// between 1 and `i32::MAX`
+pub fn trend(oldest: u32, newest: u32, threshold: u32) -> Trend {
+    let change = newest as i32 - oldest as i32;
+    if change >= threshold as i32 {
+        Trend::Rising
+    } else if change <= -(threshold as i32) {
+        Trend::Falling
+    } else {
+        Trend::Steady
+    }
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    // Zero until set, in which case the sea level pressure is used
+    reference: u32,
+    altitude_pending: bool,
+    reference_pending: bool,
+    tracking: bool,
+}
+
+impl App {
+    fn reference(&self) -> u32 {
+        if self.reference == 0 {
+            SEA_LEVEL_PRESSURE
+        } else {
+            self.reference
+        }
+    }
+
+    fn report(&self, kind: usize, data1: usize, data2: usize) {
+        self.callback.map(|mut cb| cb.schedule(kind, data1, data2));
+    }
+}
+
+pub struct Altitude<'a, B: RegisterBus + 'a, A: Alarm + 'a, T: Alarm + 'a> {
+    driver: &'a bmp280::BMP280<'a, B, A>,
+    alarm: &'a T,
+    history_pending: Cell<bool>,
+    tracking: Cell<bool>,
+    interval_ms: Cell<u32>,
+    threshold: Cell<u32>,
+    history: Cell<[u32; HISTORY_LEN]>,
+    history_len: Cell<usize>,
+    history_next: Cell<usize>,
+    apps: Grant<App>,
+}
+
+impl<'a, B: RegisterBus + 'a, A: Alarm + 'a, T: Alarm + 'a> Altitude<'a, B, A, T> {
+    pub fn new(
+        driver: &'a bmp280::BMP280<'a, B, A>,
+        alarm: &'a T,
+        grant: Grant<App>,
+    ) -> Altitude<'a, B, A, T> {
+        Altitude {
+            driver,
+            alarm,
+            history_pending: Cell::new(false),
+            tracking: Cell::new(false),
+            interval_ms: Cell::new(DEFAULT_INTERVAL_MS),
+            threshold: Cell::new(DEFAULT_TREND_THRESHOLD),
+            history: Cell::new([0; HISTORY_LEN]),
+            history_len: Cell::new(0),
+            history_next: Cell::new(0),
+            apps: grant,
+        }
+    }
+
+    /// Requests a sample on behalf of an application, `pending` selects the
+    /// flag of the request in its state
+    fn request<F>(&self, app_id: AppId, pending: F) -> ReturnCode
+    where
+        F: Fn(&mut App) -> &mut bool,
+    {
+        let requested = self.apps.enter(app_id, |app, _| {
+            let already_pending = *pending(app);
+            *pending(app) = true;
+            already_pending
+        });
+        match requested {
+            // A sample is already on its way
+            Ok(true) => return ReturnCode::SUCCESS,
+            Ok(false) => (),
+            Err(err) => return err.into(),
+        }
+
+        // In normal mode the sample is delivered before `read` returns, so
+        // the grant is not held across the call
+        let result = self.driver.read();
+        if result != ReturnCode::SUCCESS {
+            let _ = self.apps.enter(app_id, |app, _| *pending(app) = false);
+        }
+        result
+    }
+
+    fn request_history(&self) -> ReturnCode {
+        self.history_pending.set(true);
+        let result = self.driver.read();
+        if result != ReturnCode::SUCCESS {
+            self.history_pending.set(false);
+        }
+        result
+    }
+
+    // A zero threshold would make every steady history a rising one
+    fn set_threshold(&self, threshold: usize) -> ReturnCode {
+        if threshold == 0 || threshold > i32::max_value() as usize {
+            return ReturnCode::EINVAL;
+        }
+        self.threshold.set(threshold as u32);
+        ReturnCode::SUCCESS
+    }
+
+    fn set_reference(&self, reference: usize, app_id: AppId) -> ReturnCode {
+        if reference == 0 || reference > u32::max_value() as usize {
+            return ReturnCode::EINVAL;
+        }
+
+        self.apps
+            .enter(app_id, |app, _| {
+                app.reference = reference as u32;
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    fn start_tracking(&self, interval_ms: u32, app_id: AppId) -> ReturnCode {
+        if interval_ms < MIN_INTERVAL_MS || interval_ms > MAX_INTERVAL_MS {
+            return ReturnCode::EINVAL;
+        }
+
+        let result = self.apps.enter(app_id, |app, _| app.tracking = true);
+        if let Err(err) = result {
+            return err.into();
+        }
+
+        self.interval_ms.set(interval_ms);
+        if self.tracking.get() {
+            // Keep the history, only the interval changes
+            self.schedule_sample();
+            return ReturnCode::SUCCESS;
+        }
+
+        self.history_len.set(0);
+        self.history_next.set(0);
+        self.tracking.set(true);
+
+        // The first sample is taken right away
+        let result = self.request_history();
+        if result == ReturnCode::SUCCESS {
+            self.schedule_sample();
+        } else {
+            self.tracking.set(false);
+            let _ = self.apps.enter(app_id, |app, _| app.tracking = false);
+        }
+        result
+    }
+
+    fn stop_tracking(&self, app_id: AppId) -> ReturnCode {
+        let result = self.apps.enter(app_id, |app, _| app.tracking = false);
+        if let Err(err) = result {
+            return err.into();
+        }
+
+        let mut tracked = false;
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| tracked |= app.tracking);
+        }
+
+        // Sampling stops with the last application tracking the trend
+        if !tracked {
+            self.tracking.set(false);
+            self.history_pending.set(false);
+            self.alarm.disable();
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn schedule_sample(&self) {
+        let ticks = rtc::ticks_from_ms::<T::Frequency>(self.interval_ms.get());
+        self.alarm.set_alarm(self.alarm.now().wrapping_add(ticks));
+    }
+
+    fn record(&self, pressure: u32) {
+        let mut history = self.history.get();
+        let next = self.history_next.get();
+        history[next] = pressure;
+        self.history.set(history);
+        self.history_next.set((next + 1) % HISTORY_LEN);
+
+        let len = self.history_len.get();
+        if len < HISTORY_LEN {
+            self.history_len.set(len + 1);
+        }
+    }
+
+    /// Oldest sample in the history
+    fn oldest(&self) -> u32 {
+        let index = if self.history_len.get() < HISTORY_LEN {
+            0
+        } else {
+            self.history_next.get()
+        };
+        self.history.get()[index]
+    }
+
+    // Answers the pending altitude and reference requests
+    fn report_requests(&self, pressure: Option<u32>) {
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                if app.reference_pending {
+                    app.reference_pending = false;
+                    match pressure {
+                        Some(pressure) => {
+                            app.reference = pressure;
+                            app.report(3, pressure as usize, 0);
+                        }
+                        None => app.report(3, sensor::READING_FAILED, 0),
+                    }
+                }
+
+                if app.altitude_pending {
+                    app.altitude_pending = false;
+                    match pressure {
+                        Some(pressure) => {
+                            let altitude = altitude_cm(pressure, app.reference());
+                            app.report(1, altitude as isize as usize, pressure as usize);
+                        }
+                        None => app.report(1, sensor::READING_FAILED, 0),
+                    }
+                }
+            });
+        }
+    }
+
+    fn report_trend(&self, pressure: u32) {
+        self.record(pressure);
+        if self.history_len.get() < 2 {
+            return;
+        }
+
+        let oldest = self.oldest();
+        let change = pressure as i32 - oldest as i32;
+        let trend = trend(oldest, pressure, self.threshold.get());
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                if app.tracking {
+                    app.report(2, trend as usize, change as isize as usize);
+                }
+            });
+        }
+    }
+}
+
+impl<'a, B: RegisterBus + 'a, A: Alarm + 'a, T: Alarm + 'a> PressureClient for Altitude<'a, B, A, T> {
+    fn callback(&self, sample: Result<(u32, i32), I2cError>) {
+        let pressure = sample.ok().map(|(pressure, _)| pressure);
+        self.report_requests(pressure);
+
+        if self.history_pending.get() {
+            self.history_pending.set(false);
+            // A failed sample only leaves a gap in the history
+            if let (true, Some(pressure)) = (self.tracking.get(), pressure) {
+                self.report_trend(pressure);
+            }
+        }
+    }
+}
+
+impl<'a, B: RegisterBus + 'a, A: Alarm + 'a, T: Alarm + 'a> time::Client for Altitude<'a, B, A, T> {
+    fn fired(&self) {
+        if !self.tracking.get() {
+            return;
+        }
+
+        // A failed sample only leaves a gap in the history
+        self.request_history();
+        self.schedule_sample();
+    }
+}
+
+impl<'a, B: RegisterBus + 'a, A: Alarm + 'a, T: Alarm + 'a> Driver for Altitude<'a, B, A, T> {
+    fn subscribe(&self, subscribe_num: usize, callback: Option<Callback>, app_id: AppId) -> ReturnCode {
+        match subscribe_num {
+            0 => self.apps
+                .enter(app_id, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn command(&self, command_num: usize, data: usize, _: usize, app_id: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => self.request(app_id, |app| &mut app.altitude_pending),
+            2 => self.set_reference(data, app_id),
+            3 => self.request(app_id, |app| &mut app.reference_pending),
+            4 => self.start_tracking(data as u32, app_id),
+            5 => self.stop_tracking(app_id),
+            6 => self.set_threshold(data),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -0,0 +1,135 @@
+//! Provides userspace with access to the battery monitor: the supply voltage
+//! and the die temperature are each exposed through a driver of their own.
+//!
+//! Voltages are in millivolts, temperatures in signed hundredths of a
+//! degree Celsius.
+//!
+//! Commands:
+//!
+//!     * 0: driver check
+//!     * 1: read the measurement
+//!     * 2: set the lower limit (`data`)
+//!     * 3: set the upper limit (`data`)
+//!     * 4: enable limit events
+//!     * 5: disable limit events
+//!     * 6: set the interval limits are checked at (`data`, in ms, shared
+//!       by both drivers)
+//!
+//! Readings are delivered to the callback subscribed with number 0 as
+//! `callback(value, 0, 0)`.
+//!
+//! Limits outside of what the monitor can measure (0 to 7996 mV, -256 to
+//! 255 degrees) or crossing the other limit are refused with `EINVAL`.
+//!
+//! While limit events are enabled the callback subscribed with number 1 is
+//! invoked as `callback(limit, value, 0)` when the measurement leaves the
+//! window set by the limits, where limit is 1 for the upper and 2 for the
+//! lower limit. The limits and limit events are shared by all applications.
+
+use cc26x0::batmon::{Batmon, BatmonClient, Limit, Measurement};
+use cc26x0::sensor;
+use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
+use kernel::hil::time::Alarm;
+
+pub const VOLTAGE_DRIVER_NUM: usize = 0x90006;
+pub const TEMPERATURE_DRIVER_NUM: usize = 0x90007;
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    limit_callback: Option<Callback>,
+}
+
+pub struct BatmonSensor<'a, A: Alarm + 'a> {
+    batmon: &'a Batmon<'a, A>,
+    measurement: Measurement,
+    apps: Grant<App>,
+}
+
+impl<'a, A: Alarm + 'a> BatmonSensor<'a, A> {
+    pub fn new(
+        batmon: &'a Batmon<'a, A>,
+        measurement: Measurement,
+        grant: Grant<App>,
+    ) -> BatmonSensor<'a, A> {
+        BatmonSensor {
+            batmon,
+            measurement,
+            apps: grant,
+        }
+    }
+
+    fn value(&self, value: i32) -> usize {
+        match self.measurement {
+            Measurement::Voltage => value as usize,
+            Measurement::Temperature => sensor::temperature_value(value),
+        }
+    }
+
+    fn read(&self, app_id: AppId) -> ReturnCode {
+        if !self.batmon.is_enabled() {
+            return ReturnCode::EOFF;
+        }
+
+        match self.batmon.read(self.measurement) {
+            Some(value) => {
+                let value = self.value(value);
+                self.apps
+                    .enter(app_id, |app, _| {
+                        app.callback.map(|mut cb| cb.schedule(value, 0, 0));
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or_else(|err| err.into())
+            }
+            // The first measurement has not completed yet
+            None => ReturnCode::EBUSY,
+        }
+    }
+}
+
+impl<'a, A: Alarm + 'a> BatmonClient for BatmonSensor<'a, A> {
+    fn limit(&self, measurement: Measurement, limit: Limit, value: i32) {
+        if measurement != self.measurement {
+            return;
+        }
+
+        let value = self.value(value);
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                app.limit_callback
+                    .map(|mut cb| cb.schedule(limit as usize, value, 0));
+            });
+        }
+    }
+}
+
+impl<'a, A: Alarm + 'a> Driver for BatmonSensor<'a, A> {
+    fn subscribe(&self, subscribe_num: usize, callback: Option<Callback>, app_id: AppId) -> ReturnCode {
+        self.apps
+            .enter(app_id, |app, _| match subscribe_num {
+                0 => {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                }
+                1 => {
+                    app.limit_callback = callback;
+                    ReturnCode::SUCCESS
+                }
+                _ => ReturnCode::ENOSUPPORT,
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    fn command(&self, command_num: usize, data: usize, _: usize, app_id: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => self.read(app_id),
+            2 => self.batmon.set_low_limit(self.measurement, data as isize as i32),
+            3 => self.batmon.set_high_limit(self.measurement, data as isize as i32),
+            4 => self.batmon.enable_limits(self.measurement),
+            5 => self.batmon.disable_limits(self.measurement),
+            6 => self.batmon.set_poll_interval(data as u32),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -0,0 +1,214 @@
+//! I2C bus scanner
+//!
+//! Diagnostic driver which probes every 7-bit address on either SensorTag
+//! I2C interface and reports the devices that acknowledge their address.
+//! Each address is probed with a single byte read, so nothing is written to
+//! the devices found.
+//!
+//! A scan runs in the background a few addresses at a time, so that other
+//! drivers keep using the bus in between.
+//!
+//! Commands:
+//!
+//!     * 0: driver check
+//!     * 1: scan interface `data` (0 or 1)
+//!     * 2: probe address `data2` on interface `data`
+//!     * 3: returns word `data2` (0-3) of the responder bitmap of the
+//!          last scan of interface `data`, bit n is set if address
+//!          32 * `data2` + n responded
+//!
+//! The end of a scan is delivered to the callback subscribed with number 0
+//! as `callback(interface, responders, 0)`.
+
+use cc26x0::i2c::{self, I2cError, I2cInterface};
+use cc26x0::rtc;
+use core::cell::Cell;
+use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
+use kernel::hil::time::{self, Alarm};
+
+pub const DRIVER_NUM: usize = 0x90000;
+
+// Addresses outside this range are reserved by the I2C specification
+pub const FIRST_ADDRESS: u8 = 0x08;
+pub const LAST_ADDRESS: u8 = 0x77;
+
+// Number of addresses probed before giving the bus back to other drivers
+const PROBES_PER_STEP: u8 = 8;
+const STEP_INTERVAL_MS: u32 = 1;
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    pending: bool,
+}
+
+pub struct I2cScanner<'a, A: Alarm + 'a> {
+    alarm: &'a A,
+    // Interface being scanned and the next address to probe on it
+    scanning: Cell<Option<I2cInterface>>,
+    next_address: Cell<u8>,
+    found: Cell<[u32; 4]>,
+    responders: [Cell<[u32; 4]>; 2],
+    apps: Grant<App>,
+}
+
+impl<'a, A: Alarm + 'a> I2cScanner<'a, A> {
+    pub fn new(alarm: &'a A, grant: Grant<App>) -> I2cScanner<'a, A> {
+        I2cScanner {
+            alarm,
+            scanning: Cell::new(None),
+            next_address: Cell::new(FIRST_ADDRESS),
+            found: Cell::new([0; 4]),
+            responders: [Cell::new([0; 4]), Cell::new([0; 4])],
+            apps: grant,
+        }
+    }
+
+    /// Checks whether a device acknowledges `addr` on the given interface.
+    /// The interface, address and speed selected by the drivers are
+    /// restored afterwards.
+    pub fn probe(&self, interface: I2cInterface, addr: u8) -> Result<(), I2cError> {
+        unsafe {
+            let i2c = &i2c::I2C0;
+            let previous = (i2c.interface(), i2c.address(), i2c.speed());
+
+            i2c.select(interface, addr, i2c::I2cSpeed::Standard);
+            let result = i2c.read_single().map(|_| ());
+            i2c.select(previous.0, previous.1, previous.2);
+            result
+        }
+    }
+
+    fn start_scan(&self, interface: I2cInterface, app_id: AppId) -> ReturnCode {
+        match self.scanning.get() {
+            // Report the scan in progress to this application as well
+            Some(scanning) if scanning == interface => (),
+            Some(_) => return ReturnCode::EBUSY,
+            None => {
+                self.scanning.set(Some(interface));
+                self.next_address.set(FIRST_ADDRESS);
+                self.found.set([0; 4]);
+                self.schedule_step();
+            }
+        }
+
+        self.apps
+            .enter(app_id, |app, _| {
+                app.pending = true;
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    fn schedule_step(&self) {
+        let ticks = rtc::ticks_from_ms::<A::Frequency>(STEP_INTERVAL_MS);
+        self.alarm.set_alarm(self.alarm.now().wrapping_add(ticks));
+    }
+
+    // Probes the next few addresses, returns whether the scan is complete
+    fn step(&self, interface: I2cInterface) -> bool {
+        let mut found = self.found.get();
+        let first = self.next_address.get();
+        let last = LAST_ADDRESS.min(first.saturating_add(PROBES_PER_STEP - 1));
+
+        for addr in first..(last + 1) {
+            if self.probe(interface, addr).is_ok() {
+                found[(addr / 32) as usize] |= 1 << (addr % 32);
+            }
+        }
+
+        self.found.set(found);
+        self.next_address.set(last + 1);
+        last == LAST_ADDRESS
+    }
+
+    fn finish_scan(&self, interface: I2cInterface) {
+        let found = self.found.get();
+        let count = found.iter().map(|word| word.count_ones()).sum::<u32>() as usize;
+
+        self.responders[interface as usize].set(found);
+        self.scanning.set(None);
+
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                if app.pending {
+                    app.pending = false;
+                    app.callback
+                        .map(|mut cb| cb.schedule(interface as usize, count, 0));
+                }
+            });
+        }
+    }
+
+    fn interface(num: usize) -> Option<I2cInterface> {
+        match num {
+            0 => Some(I2cInterface::Interface0),
+            1 => Some(I2cInterface::Interface1),
+            _ => None,
+        }
+    }
+}
+
+impl<'a, A: Alarm + 'a> time::Client for I2cScanner<'a, A> {
+    fn fired(&self) {
+        let interface = match self.scanning.get() {
+            Some(interface) => interface,
+            None => return,
+        };
+
+        if self.step(interface) {
+            self.finish_scan(interface);
+        } else {
+            self.schedule_step();
+        }
+    }
+}
+
+impl<'a, A: Alarm + 'a> Driver for I2cScanner<'a, A> {
+    fn subscribe(&self, subscribe_num: usize, callback: Option<Callback>, app_id: AppId) -> ReturnCode {
+        match subscribe_num {
+            0 => self.apps
+                .enter(app_id, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn command(&self, command_num: usize, data: usize, data2: usize, app_id: AppId) -> ReturnCode {
+        if command_num == 0 {
+            return ReturnCode::SUCCESS;
+        }
+
+        let interface = match Self::interface(data) {
+            Some(interface) => interface,
+            None => return ReturnCode::EINVAL,
+        };
+
+        match command_num {
+            1 => self.start_scan(interface, app_id),
+            2 => {
+                if data2 < FIRST_ADDRESS as usize || data2 > LAST_ADDRESS as usize {
+                    return ReturnCode::EINVAL;
+                }
+
+                match self.probe(interface, data2 as u8) {
+                    Ok(()) => ReturnCode::SUCCESS,
+                    Err(err) => err.into(),
+                }
+            }
+            3 => {
+                if data2 >= 4 {
+                    return ReturnCode::EINVAL;
+                }
+
+                ReturnCode::SuccessWithValue {
+                    value: self.responders[interface as usize].get()[data2] as usize,
+                }
+            }
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
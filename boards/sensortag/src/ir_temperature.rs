@@ -0,0 +1,127 @@
+//! Provides userspace with access to the TMP007 infrared temperature sensor.
+//!
+//! Temperatures are in signed hundredths of a degree Celsius.
+//!
+//! Commands:
+//!
+//!     * 0: driver check
+//!     * 1: read the object temperature
+//!     * 2: read the die temperature
+//!     * 3: set the conversion rate (`data`, see `tmp007::ConversionRate`)
+//!     * 4: set the object temperature high limit (`data`, signed hundredths)
+//!     * 5: set the object temperature low limit (`data`, signed hundredths)
+//!     * 6: enable alerts
+//!     * 7: disable alerts
+//!
+//! Readings are delivered to the callback subscribed with number 0 as
+//! `callback(kind, temperature, 0)`, where kind is 1 for the object and
+//! 2 for the die temperature.
+//!
+//! While alerts are enabled the callback subscribed with number 1 is
+//! invoked as `callback(limit, temperature, 0)` when the object temperature
+//! crosses one of the limits, where limit is 1 for the high and 2 for the
+//! low limit. Limits and alerts are shared by all applications, limits
+//! outside -256.00 to 255.50 degrees are refused with `EINVAL`.
+
+use cc26x0::sensor::{self, RegisterBus};
+use cc26x0::tmp007::{self, ConversionRate, Limit, Tmp007Client, Tmp007Error};
+use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
+use kernel::hil::gpio;
+
+pub const DRIVER_NUM: usize = 0x90001;
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    alert_callback: Option<Callback>,
+}
+
+pub struct IrTemperature<'a, B: RegisterBus + 'a, P: gpio::Pin + gpio::PinCtl + 'a> {
+    driver: &'a tmp007::TMP007<'a, B, P>,
+    apps: Grant<App>,
+}
+
+impl<'a, B: RegisterBus + 'a, P: gpio::Pin + gpio::PinCtl + 'a> IrTemperature<'a, B, P> {
+    pub fn new(driver: &'a tmp007::TMP007<'a, B, P>, grant: Grant<App>) -> IrTemperature<'a, B, P> {
+        IrTemperature {
+            driver,
+            apps: grant,
+        }
+    }
+
+    fn read(&self, kind: usize, result: Result<i32, Tmp007Error>, app_id: AppId) -> ReturnCode {
+        let temp = match result {
+            Ok(temp) => temp,
+            Err(err) => return err.into(),
+        };
+
+        self.apps
+            .enter(app_id, |app, _| {
+                app.callback
+                    .map(|mut cb| cb.schedule(kind, sensor::temperature_value(temp), 0));
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+}
+
+impl<'a, B: RegisterBus + 'a, P: gpio::Pin + gpio::PinCtl + 'a> Tmp007Client for IrTemperature<'a, B, P> {
+    fn alert(&self, limit: Limit, temperature: i32) {
+        let temp = sensor::temperature_value(temperature);
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                app.alert_callback
+                    .map(|mut cb| cb.schedule(limit as usize, temp, 0));
+            });
+        }
+    }
+}
+
+impl<'a, B: RegisterBus + 'a, P: gpio::Pin + gpio::PinCtl + 'a> Driver for IrTemperature<'a, B, P> {
+    fn subscribe(&self, subscribe_num: usize, callback: Option<Callback>, app_id: AppId) -> ReturnCode {
+        self.apps
+            .enter(app_id, |app, _| match subscribe_num {
+                0 => {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                }
+                1 => {
+                    app.alert_callback = callback;
+                    ReturnCode::SUCCESS
+                }
+                _ => ReturnCode::ENOSUPPORT,
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    fn command(&self, command_num: usize, data: usize, _: usize, app_id: AppId) -> ReturnCode {
+        if command_num == 0 {
+            return ReturnCode::SUCCESS;
+        }
+
+        if !self.driver.is_present() {
+            return ReturnCode::ENODEVICE;
+        }
+
+        match command_num {
+            1 => self.read(1, self.driver.object_temperature(), app_id),
+            2 => self.read(2, self.driver.die_temperature(), app_id),
+            3 => match ConversionRate::from_usize(data) {
+                Some(rate) => result_code(self.driver.set_conversion_rate(rate)),
+                None => ReturnCode::EINVAL,
+            },
+            4 => result_code(self.driver.set_high_limit(data as isize as i32)),
+            5 => result_code(self.driver.set_low_limit(data as isize as i32)),
+            6 => result_code(self.driver.enable_alerts()),
+            7 => result_code(self.driver.disable_alerts()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+fn result_code<E: Into<ReturnCode>>(result: Result<(), E>) -> ReturnCode {
+    match result {
+        Ok(()) => ReturnCode::SUCCESS,
+        Err(err) => err.into(),
+    }
+}
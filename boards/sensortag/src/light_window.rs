@@ -0,0 +1,96 @@
+//! Provides userspace with the OPT3001 light limit window.
+//!
+//! Commands:
+//!
+//!     * 0: driver check
+//!     * 1: set the low limit (`data`, in hundredths of a lux)
+//!     * 2: set the high limit (`data`, in hundredths of a lux)
+//!     * 3: enable the limit window
+//!     * 4: disable the limit window
+//!
+//! While the window is enabled the callback subscribed with number 0 is
+//! invoked as `callback(limit, lux, 0)` when the light level leaves the
+//! window, where limit is 1 for the high and 2 for the low limit. The limits
+//! and the window are shared by all applications.
+
+use cc26x0::i2c::I2cError;
+use cc26x0::opt3001::{self, Limit, Opt3001Client};
+use cc26x0::sensor::RegisterBus;
+use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
+use kernel::hil::gpio;
+use kernel::hil::time::Alarm;
+
+pub const DRIVER_NUM: usize = 0x90004;
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+}
+
+pub struct LightWindow<'a, B: RegisterBus + 'a, A: Alarm + 'a, P: gpio::Pin + gpio::PinCtl + 'a> {
+    driver: &'a opt3001::OPT3001<'a, B, A, P>,
+    apps: Grant<App>,
+}
+
+impl<'a, B: RegisterBus + 'a, A: Alarm + 'a, P: gpio::Pin + gpio::PinCtl + 'a> LightWindow<'a, B, A, P> {
+    pub fn new(driver: &'a opt3001::OPT3001<'a, B, A, P>, grant: Grant<App>) -> LightWindow<'a, B, A, P> {
+        LightWindow {
+            driver,
+            apps: grant,
+        }
+    }
+}
+
+impl<'a, B: RegisterBus + 'a, A: Alarm + 'a, P: gpio::Pin + gpio::PinCtl + 'a> Opt3001Client
+    for LightWindow<'a, B, A, P>
+{
+    fn left_window(&self, limit: Limit, lux: u32) {
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                app.callback
+                    .map(|mut cb| cb.schedule(limit as usize, lux as usize, 0));
+            });
+        }
+    }
+}
+
+impl<'a, B: RegisterBus + 'a, A: Alarm + 'a, P: gpio::Pin + gpio::PinCtl + 'a> Driver
+    for LightWindow<'a, B, A, P>
+{
+    fn subscribe(&self, subscribe_num: usize, callback: Option<Callback>, app_id: AppId) -> ReturnCode {
+        match subscribe_num {
+            0 => self.apps
+                .enter(app_id, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn command(&self, command_num: usize, data: usize, _: usize, _: AppId) -> ReturnCode {
+        if command_num == 0 {
+            return ReturnCode::SUCCESS;
+        }
+
+        if !self.driver.is_present() {
+            return ReturnCode::ENODEVICE;
+        }
+
+        match command_num {
+            1 => result_code(self.driver.set_low_limit(data as u32)),
+            2 => result_code(self.driver.set_high_limit(data as u32)),
+            3 => self.driver.enable_window(),
+            4 => self.driver.disable_window(),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+fn result_code(result: Result<(), I2cError>) -> ReturnCode {
+    match result {
+        Ok(()) => ReturnCode::SUCCESS,
+        Err(err) => err.into(),
+    }
+}
@@ -0,0 +1,283 @@
+//! Low battery policy driven by the battery monitor.
+//!
+//! The supply voltage is sampled at a fixed interval and classified against
+//! two thresholds:
+//!
+//!     * below the low threshold, applications are notified and BLE
+//!       advertising is reduced to one in every few advertising events
+//!     * below the critical threshold, applications are notified and the
+//!       chip enters shutdown after a grace period, before a brown-out can
+//!       corrupt flash writes in progress
+//!
+//! The voltage has to rise a little above a threshold before the level is
+//! raised again, see `batmon::supply_level`. Shutdown only happens if the
+//! voltage is still critical at the end of the grace period: sampling goes
+//! on at the interval meanwhile, and recovering cancels the shutdown. New
+//! thresholds apply to the latest voltage right away.
+//!
+//! The thresholds, interval and advertising divider are shared by all
+//! applications.
+//!
+//! Commands:
+//!
+//!     * 0: driver check
+//!     * 1: read the level and voltage
+//!     * 2: set the low threshold (`data`, in mV)
+//!     * 3: set the critical threshold (`data`, in mV)
+//!     * 4: set the sampling interval (`data`, in ms)
+//!     * 5: set the advertising divider while low (`data`, 1 transmits
+//!       every advertising event)
+//!
+//! Levels are delivered to the callback subscribed with number 0 as
+//! `callback(level, voltage, 0)`, with level 0 for normal, 1 for low and 2
+//! for critical and the voltage in mV. The callback of the application
+//! reading the level is invoked, and those of all applications whenever the
+//! level changes. Once the level is critical
+//! applications have the grace period to save their state.
+
+use cc26x0::aux;
+use cc26x0::batmon::{self, Batmon, SupplyDeadlines, SupplyLevel};
+use cc26x0::radio::ble::Ble;
+use cc26x0::rtc;
+use core::cell::Cell;
+use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
+use kernel::hil::time::{self, Alarm, Time};
+
+pub const DRIVER_NUM: usize = 0x90008;
+
+pub const DEFAULT_LOW_MV: u32 = 2500;
+pub const DEFAULT_CRITICAL_MV: u32 = 2200;
+
+pub const DEFAULT_INTERVAL_MS: u32 = 60 * 1000;
+pub const MIN_INTERVAL_MS: u32 = 1000;
+pub const MAX_INTERVAL_MS: u32 = 60 * 60 * 1000;
+
+pub const SHUTDOWN_GRACE_MS: u32 = 2000;
+
+pub const DEFAULT_ADVERTISING_DIVIDER: usize = 4;
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+}
+
+pub struct LowBattery<'a, A: Alarm + 'a, T: Alarm + 'a> {
+    batmon: &'a Batmon<'a, A>,
+    ble: &'a Ble,
+    alarm: &'a T,
+    level: Cell<SupplyLevel>,
+    voltage: Cell<u32>,
+    low_mv: Cell<u32>,
+    critical_mv: Cell<u32>,
+    interval_ms: Cell<u32>,
+    advertising_divider: Cell<usize>,
+    deadlines: Cell<SupplyDeadlines>,
+    apps: Grant<App>,
+}
+
+impl<'a, A: Alarm + 'a, T: Alarm + 'a> LowBattery<'a, A, T> {
+    pub fn new(
+        batmon: &'a Batmon<'a, A>,
+        ble: &'a Ble,
+        alarm: &'a T,
+        grant: Grant<App>,
+    ) -> LowBattery<'a, A, T> {
+        LowBattery {
+            batmon,
+            ble,
+            alarm,
+            level: Cell::new(SupplyLevel::Normal),
+            voltage: Cell::new(0),
+            low_mv: Cell::new(DEFAULT_LOW_MV),
+            critical_mv: Cell::new(DEFAULT_CRITICAL_MV),
+            interval_ms: Cell::new(DEFAULT_INTERVAL_MS),
+            advertising_divider: Cell::new(DEFAULT_ADVERTISING_DIVIDER),
+            deadlines: Cell::new(SupplyDeadlines::new(0)),
+            apps: grant,
+        }
+    }
+
+    /// Starts sampling, the battery monitor must be enabled
+    pub fn start(&self) {
+        self.schedule_sample();
+        self.rearm();
+    }
+
+    /// Level of a voltage, given the current level for the hysteresis
+    pub fn classify(&self, voltage: u32, current: SupplyLevel) -> SupplyLevel {
+        batmon::supply_level(voltage, current, self.low_mv.get(), self.critical_mv.get())
+    }
+
+    fn update_deadlines<F>(&self, update: F)
+    where
+        F: FnOnce(&mut SupplyDeadlines),
+    {
+        let mut deadlines = self.deadlines.get();
+        update(&mut deadlines);
+        self.deadlines.set(deadlines);
+    }
+
+    fn schedule_sample(&self) {
+        let ticks = rtc::ticks_from_ms::<T::Frequency>(self.interval_ms.get());
+        let due = self.alarm.now().wrapping_add(ticks);
+        self.update_deadlines(|deadlines| deadlines.set_sample(due));
+    }
+
+    // The alarm serves both the sampling interval and the grace period
+    fn rearm(&self) {
+        let now = self.alarm.now();
+        self.alarm.set_alarm(self.deadlines.get().next(now));
+    }
+
+    /// Returns whether the level has changed, which has been reported
+    fn sample(&self) -> bool {
+        let voltage = match self.batmon.voltage() {
+            Some(voltage) => voltage,
+            // No measurement yet, try again at the next sample
+            None => return false,
+        };
+        self.voltage.set(voltage);
+
+        let level = self.classify(voltage, self.level.get());
+        if level == self.level.get() {
+            return false;
+        }
+        self.set_level(level);
+        true
+    }
+
+    fn set_level(&self, level: SupplyLevel) {
+        self.level.set(level);
+
+        let divider = match level {
+            SupplyLevel::Normal => 1,
+            SupplyLevel::Low | SupplyLevel::Critical => self.advertising_divider.get(),
+        };
+        self.ble.set_advertising_divider(divider);
+
+        // Leave applications time to save their state
+        let now = self.alarm.now();
+        let grace = rtc::ticks_from_ms::<T::Frequency>(SHUTDOWN_GRACE_MS);
+        self.update_deadlines(|deadlines| deadlines.level_changed(level, now, grace));
+        self.rearm();
+
+        self.report();
+    }
+
+    fn report(&self) {
+        let level = self.level.get() as usize;
+        let voltage = self.voltage.get() as usize;
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                app.callback.map(|mut cb| cb.schedule(level, voltage, 0));
+            });
+        }
+    }
+
+    // A changed level has been reported to every application already
+    fn read(&self, app_id: AppId) -> ReturnCode {
+        if self.sample() {
+            return ReturnCode::SUCCESS;
+        }
+
+        let level = self.level.get() as usize;
+        let voltage = self.voltage.get() as usize;
+        self.apps
+            .enter(app_id, |app, _| {
+                app.callback.map(|mut cb| cb.schedule(level, voltage, 0));
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    fn set_low_threshold(&self, mv: u32) -> ReturnCode {
+        if mv <= self.critical_mv.get() {
+            return ReturnCode::EINVAL;
+        }
+        self.low_mv.set(mv);
+        self.sample();
+        ReturnCode::SUCCESS
+    }
+
+    fn set_critical_threshold(&self, mv: u32) -> ReturnCode {
+        if mv == 0 || mv >= self.low_mv.get() {
+            return ReturnCode::EINVAL;
+        }
+        self.critical_mv.set(mv);
+        self.sample();
+        ReturnCode::SUCCESS
+    }
+
+    fn set_interval(&self, interval_ms: u32) -> ReturnCode {
+        if interval_ms < MIN_INTERVAL_MS || interval_ms > MAX_INTERVAL_MS {
+            return ReturnCode::EINVAL;
+        }
+
+        self.interval_ms.set(interval_ms);
+        self.schedule_sample();
+        self.rearm();
+        ReturnCode::SUCCESS
+    }
+
+    fn set_advertising_divider(&self, divider: usize) -> ReturnCode {
+        if divider == 0 {
+            return ReturnCode::EINVAL;
+        }
+
+        self.advertising_divider.set(divider);
+        if self.level.get() != SupplyLevel::Normal {
+            self.ble.set_advertising_divider(divider);
+        }
+        ReturnCode::SUCCESS
+    }
+}
+
+impl<'a, A: Alarm + 'a, T: Alarm + 'a> time::Client for LowBattery<'a, A, T> {
+    fn fired(&self) {
+        let now = self.alarm.now();
+        let deadlines = self.deadlines.get();
+        let sample_expired = deadlines.sample_expired(now);
+        let grace_expired = deadlines.grace_expired(now);
+        if !sample_expired && !grace_expired {
+            self.rearm();
+            return;
+        }
+
+        if sample_expired {
+            self.schedule_sample();
+        }
+        self.sample();
+
+        // The grace period is over and the voltage has not recovered
+        if self.deadlines.get().shutdown(now, self.level.get()) {
+            aux::AUX_CTL.shutdown();
+        }
+        self.rearm();
+    }
+}
+
+impl<'a, A: Alarm + 'a, T: Alarm + 'a> Driver for LowBattery<'a, A, T> {
+    fn subscribe(&self, subscribe_num: usize, callback: Option<Callback>, app_id: AppId) -> ReturnCode {
+        match subscribe_num {
+            0 => self.apps
+                .enter(app_id, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn command(&self, command_num: usize, data: usize, _: usize, app_id: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => self.read(app_id),
+            2 => self.set_low_threshold(data as u32),
+            3 => self.set_critical_threshold(data as u32),
+            4 => self.set_interval(data as u32),
+            5 => self.set_advertising_divider(data),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -13,10 +13,18 @@ extern crate cc26xx;
 extern crate kernel;
 
 use cc26xx::{aon,trng};
-use cc26x0::{radio,rtc,uart,gpio};
+use cc26x0::{radio,rtc,uart,gpio,i2c,hdc,tmp007,bmp280,opt3001,mpu9250,batmon};
 
 #[macro_use]
 pub mod io;
+pub mod i2c_scanner;
+pub mod ir_temperature;
+pub mod light_window;
+pub mod motion;
+pub mod pressure;
+pub mod altitude;
+pub mod battery;
+pub mod low_battery;
 
 // How should the kernel respond when a process faults.
 const FAULT_RESPONSE: kernel::process::FaultResponse = kernel::process::FaultResponse::Panic;
@@ -44,6 +52,51 @@ pub struct Platform {
         capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
     >,
     rng: &'static capsules::rng::SimpleRng<'static, trng::Trng>,
+    i2c_scanner: &'static i2c_scanner::I2cScanner<
+        'static,
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+    >,
+    temperature: &'static capsules::temperature::TemperatureSensor<'static>,
+    humidity: &'static capsules::humidity::HumiditySensor<'static>,
+    ir_temperature: &'static ir_temperature::IrTemperature<'static, i2c::I2C, gpio::GPIOPin>,
+    ambient_light: &'static capsules::ambient_light::AmbientLight<'static>,
+    light_window: &'static light_window::LightWindow<
+        'static,
+        i2c::I2C,
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+        gpio::GPIOPin,
+    >,
+    ninedof: &'static capsules::ninedof::NineDof<'static>,
+    motion: &'static motion::Motion<
+        'static,
+        i2c::I2C,
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+        gpio::GPIOPin,
+    >,
+    pressure: &'static pressure::PressureSensor<
+        'static,
+        i2c::I2C,
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+    >,
+    altitude: &'static altitude::Altitude<
+        'static,
+        i2c::I2C,
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+    >,
+    battery_voltage: &'static battery::BatmonSensor<
+        'static,
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+    >,
+    die_temperature: &'static battery::BatmonSensor<
+        'static,
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+    >,
+    low_battery: &'static low_battery::LowBattery<
+        'static,
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+    >,
 }
 
 impl kernel::Platform for Platform {
@@ -59,6 +112,19 @@ impl kernel::Platform for Platform {
             capsules::alarm::DRIVER_NUM => f(Some(self.alarm)),
             capsules::ble_advertising_driver::DRIVER_NUM => f(Some(self.ble_radio)),
             capsules::rng::DRIVER_NUM => f(Some(self.rng)),
+            i2c_scanner::DRIVER_NUM => f(Some(self.i2c_scanner)),
+            capsules::temperature::DRIVER_NUM => f(Some(self.temperature)),
+            capsules::humidity::DRIVER_NUM => f(Some(self.humidity)),
+            ir_temperature::DRIVER_NUM => f(Some(self.ir_temperature)),
+            capsules::ambient_light::DRIVER_NUM => f(Some(self.ambient_light)),
+            light_window::DRIVER_NUM => f(Some(self.light_window)),
+            capsules::ninedof::DRIVER_NUM => f(Some(self.ninedof)),
+            motion::DRIVER_NUM => f(Some(self.motion)),
+            pressure::DRIVER_NUM => f(Some(self.pressure)),
+            altitude::DRIVER_NUM => f(Some(self.altitude)),
+            battery::VOLTAGE_DRIVER_NUM => f(Some(self.battery_voltage)),
+            battery::TEMPERATURE_DRIVER_NUM => f(Some(self.die_temperature)),
+            low_battery::DRIVER_NUM => f(Some(self.low_battery)),
             _ => f(None),
         }
     }
@@ -137,18 +203,15 @@ pub unsafe fn reset_handler() {
 
     // Setup for remaining GPIO pins
     let gpio_pins = static_init!(
-        [&'static gpio::GPIOPin; 26],
+        [&'static gpio::GPIOPin; 23],
         [
-            &gpio::PORT[1],
             &gpio::PORT[2],
             &gpio::PORT[3],
             &gpio::PORT[5],
             &gpio::PORT[6],
-            &gpio::PORT[7],
             &gpio::PORT[8],
             &gpio::PORT[9],
             &gpio::PORT[11],
-            &gpio::PORT[12],
             &gpio::PORT[13],
             &gpio::PORT[14],
             &gpio::PORT[16],
@@ -178,6 +241,8 @@ pub unsafe fn reset_handler() {
     let rtc = &rtc::RTC;
     rtc.start();
 
+    // Alarms tick at 65536 Hz (`rtc::Freq65KHz`), the capsules and drivers
+    // below all convert their times through the `Frequency` of the alarm
     let mux_alarm = static_init!(
         capsules::virtual_alarm::MuxAlarm<'static, rtc::Rtc>,
         capsules::virtual_alarm::MuxAlarm::new(&rtc::RTC)
@@ -233,6 +298,241 @@ pub unsafe fn reset_handler() {
     );
     ble_radio_virtual_alarm.set_client(ble_radio);
 
+    // Bounds the interrupt driven I2C transfers
+    let i2c_virtual_alarm = static_init!(
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+        capsules::virtual_alarm::VirtualMuxAlarm::new(mux_alarm)
+    );
+    i2c_virtual_alarm.set_client(&i2c::I2C0);
+    i2c::I2C0.set_timeout_alarm(i2c_virtual_alarm);
+
+    let i2c_scanner_virtual_alarm = static_init!(
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+        capsules::virtual_alarm::VirtualMuxAlarm::new(mux_alarm)
+    );
+    let i2c_scanner = static_init!(
+        i2c_scanner::I2cScanner<'static, capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>>,
+        i2c_scanner::I2cScanner::new(i2c_scanner_virtual_alarm, kernel::Grant::create())
+    );
+    i2c_scanner_virtual_alarm.set_client(i2c_scanner);
+
+    // HDC1000 temperature and humidity sensor
+    let hdc_virtual_alarm = static_init!(
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+        capsules::virtual_alarm::VirtualMuxAlarm::new(mux_alarm)
+    );
+    let hdc = static_init!(
+        hdc::HDC<'static, i2c::I2C, capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>>,
+        hdc::HDC::new(&i2c::I2C0, hdc_virtual_alarm)
+    );
+    hdc_virtual_alarm.set_client(hdc);
+    if hdc.init() != kernel::ReturnCode::SUCCESS {
+        debug!("HDC1000 not found\r");
+    }
+
+    let temperature = static_init!(
+        capsules::temperature::TemperatureSensor<'static>,
+        capsules::temperature::TemperatureSensor::new(hdc, kernel::Grant::create())
+    );
+    kernel::hil::sensors::TemperatureDriver::set_client(hdc, temperature);
+
+    let humidity = static_init!(
+        capsules::humidity::HumiditySensor<'static>,
+        capsules::humidity::HumiditySensor::new(hdc, kernel::Grant::create())
+    );
+    kernel::hil::sensors::HumidityDriver::set_client(hdc, humidity);
+
+    // TMP007 infrared temperature sensor
+    let tmp007 = static_init!(
+        tmp007::TMP007<'static, i2c::I2C, gpio::GPIOPin>,
+        tmp007::TMP007::new(&i2c::I2C0, &gpio::PORT[1]) // TMP_RDY (ALERT)
+    );
+    gpio::PORT[1].set_client(tmp007);
+    if tmp007.init() != kernel::ReturnCode::SUCCESS {
+        debug!("TMP007 not found\r");
+    }
+
+    let ir_temperature = static_init!(
+        ir_temperature::IrTemperature<'static, i2c::I2C, gpio::GPIOPin>,
+        ir_temperature::IrTemperature::new(tmp007, kernel::Grant::create())
+    );
+    tmp007.set_client(ir_temperature);
+
+    // OPT3001 ambient light sensor
+    let opt3001_virtual_alarm = static_init!(
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+        capsules::virtual_alarm::VirtualMuxAlarm::new(mux_alarm)
+    );
+    // INT is not routed to the MCU on the SensorTag, so the limit window
+    // is polled
+    let opt3001 = static_init!(
+        opt3001::OPT3001<
+            'static,
+            i2c::I2C,
+            capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+            gpio::GPIOPin,
+        >,
+        opt3001::OPT3001::new(&i2c::I2C0, opt3001_virtual_alarm, None)
+    );
+    opt3001_virtual_alarm.set_client(opt3001);
+    if opt3001.init() != kernel::ReturnCode::SUCCESS {
+        debug!("OPT3001 not found\r");
+    }
+
+    let ambient_light = static_init!(
+        capsules::ambient_light::AmbientLight<'static>,
+        capsules::ambient_light::AmbientLight::new(opt3001, kernel::Grant::create())
+    );
+    kernel::hil::sensors::AmbientLight::set_client(opt3001, ambient_light);
+
+    let light_window = static_init!(
+        light_window::LightWindow<
+            'static,
+            i2c::I2C,
+            capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+            gpio::GPIOPin,
+        >,
+        light_window::LightWindow::new(opt3001, kernel::Grant::create())
+    );
+    opt3001.set_window_client(light_window);
+
+    // MPU-9250 motion sensor. The driver switches MPU_POWER (DIO12) and
+    // keeps the chip powered only while it is in use.
+    let mpu_virtual_alarm = static_init!(
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+        capsules::virtual_alarm::VirtualMuxAlarm::new(mux_alarm)
+    );
+    let mpu = static_init!(
+        mpu9250::MPU9250<
+            'static,
+            i2c::I2C,
+            capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+            gpio::GPIOPin,
+        >,
+        mpu9250::MPU9250::new(
+            &i2c::I2C0,
+            mpu_virtual_alarm,
+            &gpio::PORT[7],  // MPU_INT
+            &gpio::PORT[12], // MPU_POWER
+        )
+    );
+    mpu_virtual_alarm.set_client(mpu);
+    gpio::PORT[7].set_client(mpu);
+    if mpu.init() != kernel::ReturnCode::SUCCESS {
+        debug!("MPU-9250 not found\r");
+    }
+
+    let ninedof = static_init!(
+        capsules::ninedof::NineDof<'static>,
+        capsules::ninedof::NineDof::new(mpu, kernel::Grant::create())
+    );
+    kernel::hil::sensors::NineDof::set_client(mpu, ninedof);
+
+    let motion = static_init!(
+        motion::Motion<
+            'static,
+            i2c::I2C,
+            capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+            gpio::GPIOPin,
+        >,
+        motion::Motion::new(mpu, kernel::Grant::create())
+    );
+    mpu.set_motion_client(motion);
+
+    // BMP280 pressure sensor
+    let bmp280_virtual_alarm = static_init!(
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+        capsules::virtual_alarm::VirtualMuxAlarm::new(mux_alarm)
+    );
+    let bmp280 = static_init!(
+        bmp280::BMP280<'static, i2c::I2C, capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>>,
+        bmp280::BMP280::new(&i2c::I2C0, bmp280_virtual_alarm)
+    );
+    bmp280_virtual_alarm.set_client(bmp280);
+    if bmp280.init() != kernel::ReturnCode::SUCCESS {
+        debug!("BMP280 not found\r");
+    }
+
+    let pressure = static_init!(
+        pressure::PressureSensor<
+            'static,
+            i2c::I2C,
+            capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+        >,
+        pressure::PressureSensor::new(bmp280, kernel::Grant::create())
+    );
+    bmp280.add_client(pressure);
+
+    // Altitude and pressure trends from the BMP280 samples
+    let altitude_virtual_alarm = static_init!(
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+        capsules::virtual_alarm::VirtualMuxAlarm::new(mux_alarm)
+    );
+    let altitude = static_init!(
+        altitude::Altitude<
+            'static,
+            i2c::I2C,
+            capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+            capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+        >,
+        altitude::Altitude::new(bmp280, altitude_virtual_alarm, kernel::Grant::create())
+    );
+    altitude_virtual_alarm.set_client(altitude);
+    bmp280.add_client(altitude);
+
+    // Supply voltage and die temperature from the AON battery monitor
+    let batmon_virtual_alarm = static_init!(
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+        capsules::virtual_alarm::VirtualMuxAlarm::new(mux_alarm)
+    );
+    let batmon = static_init!(
+        batmon::Batmon<'static, capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>>,
+        batmon::Batmon::new(batmon_virtual_alarm)
+    );
+    batmon_virtual_alarm.set_client(batmon);
+    batmon.enable();
+
+    let battery_voltage = static_init!(
+        battery::BatmonSensor<'static, capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>>,
+        battery::BatmonSensor::new(
+            batmon,
+            batmon::Measurement::Voltage,
+            kernel::Grant::create(),
+        )
+    );
+    batmon.add_client(battery_voltage);
+
+    let die_temperature = static_init!(
+        battery::BatmonSensor<'static, capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>>,
+        battery::BatmonSensor::new(
+            batmon,
+            batmon::Measurement::Temperature,
+            kernel::Grant::create(),
+        )
+    );
+    batmon.add_client(die_temperature);
+
+    // Reduces advertising and shuts down as the battery runs out
+    let low_battery_virtual_alarm = static_init!(
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+        capsules::virtual_alarm::VirtualMuxAlarm::new(mux_alarm)
+    );
+    let low_battery = static_init!(
+        low_battery::LowBattery<
+            'static,
+            capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+            capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+        >,
+        low_battery::LowBattery::new(
+            batmon,
+            &radio::BLE,
+            low_battery_virtual_alarm,
+            kernel::Grant::create(),
+        )
+    );
+    low_battery_virtual_alarm.set_client(low_battery);
+    low_battery.start();
+
     let sensortag = Platform {
         ble_radio,
         gpio,
@@ -241,6 +541,19 @@ pub unsafe fn reset_handler() {
         console,
         alarm,
         rng,
+        i2c_scanner,
+        temperature,
+        humidity,
+        ir_temperature,
+        ambient_light,
+        light_window,
+        ninedof,
+        motion,
+        pressure,
+        altitude,
+        battery_voltage,
+        die_temperature,
+        low_battery,
     };
 
     let mut chip = cc26x0::chip::Cc26x0::new();
@@ -0,0 +1,250 @@
+//! Provides userspace with MPU-9250 wake-on-motion and burst sampling.
+//!
+//! Commands:
+//!
+//!     * 0: driver check
+//!     * 1: enable wake-on-motion (`data`, threshold in milli-g up to 1020,
+//!       `data2`, low power output data rate, see `mpu9250::LowPowerRate`)
+//!     * 2: disable wake-on-motion
+//!     * 3: set the sample rate divider (`data`, see
+//!       `mpu9250::MPU9250::set_sample_rate_divider`)
+//!     * 4: set the accelerometer range (`data`, see `mpu9250::AccelRange`)
+//!     * 5: set the gyroscope range (`data`, see `mpu9250::GyroRange`)
+//!     * 6: start burst sampling (`data`, 1 for acceleration, 2 for rotation,
+//!       3 for both, `data2`, watermark in samples)
+//!     * 7: stop burst sampling
+//!
+//! Enabling wake-on-motion or burst sampling may have to wait for the chip
+//! to power up: once it has completed the callback subscribed with number 2
+//! is invoked as `callback(feature, result, 0)`, where feature is 1 for
+//! wake-on-motion and 2 for burst sampling and result is 0 on success or a
+//! negative error code. Disabling a feature before then cancels it with
+//! `ECANCEL`.
+//!
+//! The callback subscribed with number 0 is invoked as `callback(0, 0, 0)`
+//! when motion is detected. The callback subscribed with number 1 is
+//! invoked as `callback(samples, overflow, 0)` when a batch has been copied
+//! to the buffer allowed with number 0, overflow being 1 if samples were
+//! lost before this batch. A batch is copied to every application that has
+//! allowed a buffer, as many whole samples as fit in it.
+//!
+//! Wake-on-motion, burst sampling and the settings are shared by all
+//! applications. Burst sampling refuses to start with `ENOMEM` if a batch
+//! does not fit in the buffer of the application starting it.
+
+use cc26x0::i2c::I2cError;
+use cc26x0::mpu9250::{self, AccelRange, Feature, GyroRange, LowPowerRate, Mpu9250Client};
+use cc26x0::sensor::RegisterBus;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+use kernel::hil::gpio;
+use kernel::hil::time::Alarm;
+
+pub const DRIVER_NUM: usize = 0x90005;
+
+#[derive(Default)]
+pub struct App {
+    motion_callback: Option<Callback>,
+    fifo_callback: Option<Callback>,
+    enabled_callback: Option<Callback>,
+    // Waiting for a feature to be enabled
+    enabling: bool,
+    buffer: Option<AppSlice<Shared, u8>>,
+    // Bytes of the current batch copied to the buffer
+    batch_len: usize,
+}
+
+pub struct Motion<'a, B: RegisterBus + 'a, A: Alarm + 'a, P: gpio::Pin + gpio::PinCtl + 'a> {
+    driver: &'a mpu9250::MPU9250<'a, B, A, P>,
+    apps: Grant<App>,
+}
+
+impl<'a, B: RegisterBus + 'a, A: Alarm + 'a, P: gpio::Pin + gpio::PinCtl + 'a> Motion<'a, B, A, P> {
+    pub fn new(driver: &'a mpu9250::MPU9250<'a, B, A, P>, grant: Grant<App>) -> Motion<'a, B, A, P> {
+        Motion {
+            driver,
+            apps: grant,
+        }
+    }
+
+    // Tells the application when the feature started by `enable` is enabled
+    fn enable<F>(&self, app_id: AppId, enable: F) -> ReturnCode
+    where
+        F: FnOnce() -> ReturnCode,
+    {
+        let result = enable();
+        if result == ReturnCode::SUCCESS {
+            let _ = self.apps.enter(app_id, |app, _| app.enabling = true);
+        }
+        result
+    }
+
+    fn enable_fifo(&self, sensors: usize, watermark: usize, app_id: AppId) -> ReturnCode {
+        let mut fifo_enable = 0;
+        if sensors & 1 != 0 {
+            fifo_enable |= mpu9250::MPU_FIFO_EN_ACCEL;
+        }
+        if sensors & 2 != 0 {
+            fifo_enable |= mpu9250::MPU_FIFO_EN_GYRO;
+        }
+
+        // The batch has to fit in the buffer of the application
+        let sample_len = mpu9250::fifo_sample_len(fifo_enable);
+        let fits = self.apps.enter(app_id, |app, _| {
+            let buffer_len = app.buffer.as_ref().map_or(0, |buffer| buffer.len());
+            watermark.saturating_mul(sample_len) <= buffer_len
+        });
+        match fits {
+            Ok(true) => (),
+            Ok(false) => return ReturnCode::ENOMEM,
+            Err(err) => return err.into(),
+        }
+
+        self.enable(app_id, || self.driver.enable_fifo(fifo_enable, watermark))
+    }
+}
+
+impl<'a, B: RegisterBus + 'a, A: Alarm + 'a, P: gpio::Pin + gpio::PinCtl + 'a> Mpu9250Client
+    for Motion<'a, B, A, P>
+{
+    fn enabled(&self, feature: Feature, result: ReturnCode) {
+        let result = isize::from(result) as usize;
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                if app.enabling {
+                    app.enabling = false;
+                    app.enabled_callback
+                        .map(|mut cb| cb.schedule(feature as usize, result, 0));
+                }
+            });
+        }
+    }
+
+    fn motion(&self) {
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                app.motion_callback.map(|mut cb| cb.schedule(0, 0, 0));
+            });
+        }
+    }
+
+    fn fifo_data(&self, offset: usize, data: &[u8]) {
+        let sample_len = self.driver.sample_len();
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                let mut batch_len = app.batch_len;
+                app.buffer.as_mut().map(|buffer| {
+                    let capacity = buffer.len() - buffer.len() % sample_len;
+                    if offset >= capacity {
+                        return;
+                    }
+
+                    let end = if offset + data.len() < capacity {
+                        offset + data.len()
+                    } else {
+                        capacity
+                    };
+                    buffer.as_mut()[offset..end].copy_from_slice(&data[..end - offset]);
+                    batch_len = end;
+                });
+                app.batch_len = batch_len;
+            });
+        }
+    }
+
+    fn fifo_batch(&self, _samples: usize, overflow: bool) {
+        let sample_len = self.driver.sample_len();
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                let samples = app.batch_len / sample_len;
+                app.batch_len = 0;
+                if samples > 0 {
+                    app.fifo_callback
+                        .map(|mut cb| cb.schedule(samples, overflow as usize, 0));
+                }
+            });
+        }
+    }
+}
+
+impl<'a, B: RegisterBus + 'a, A: Alarm + 'a, P: gpio::Pin + gpio::PinCtl + 'a> Driver
+    for Motion<'a, B, A, P>
+{
+    fn subscribe(&self, subscribe_num: usize, callback: Option<Callback>, app_id: AppId) -> ReturnCode {
+        self.apps
+            .enter(app_id, |app, _| match subscribe_num {
+                0 => {
+                    app.motion_callback = callback;
+                    ReturnCode::SUCCESS
+                }
+                1 => {
+                    app.fifo_callback = callback;
+                    ReturnCode::SUCCESS
+                }
+                2 => {
+                    app.enabled_callback = callback;
+                    ReturnCode::SUCCESS
+                }
+                _ => ReturnCode::ENOSUPPORT,
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    fn allow(&self, app_id: AppId, allow_num: usize, slice: Option<AppSlice<Shared, u8>>) -> ReturnCode {
+        match allow_num {
+            0 => self.apps
+                .enter(app_id, |app, _| {
+                    app.buffer = slice;
+                    app.batch_len = 0;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn command(&self, command_num: usize, data: usize, data2: usize, app_id: AppId) -> ReturnCode {
+        if command_num == 0 {
+            return ReturnCode::SUCCESS;
+        }
+
+        if !self.driver.is_present() {
+            return ReturnCode::ENODEVICE;
+        }
+
+        match command_num {
+            1 => match LowPowerRate::from_usize(data2) {
+                Some(rate) => self.enable(app_id, || self.driver.enable_wake_on_motion(data as u32, rate)),
+                None => ReturnCode::EINVAL,
+            },
+            2 => result_code(self.driver.disable_wake_on_motion()),
+            3 => {
+                // The batch period is derived from the sample rate
+                if self.driver.fifo_enabled() {
+                    return ReturnCode::EBUSY;
+                }
+                if data > 0xFF {
+                    return ReturnCode::EINVAL;
+                }
+                result_code(self.driver.set_sample_rate_divider(data as u8))
+            }
+            4 => match AccelRange::from_usize(data) {
+                Some(range) => result_code(self.driver.set_accel_range(range)),
+                None => ReturnCode::EINVAL,
+            },
+            5 => match GyroRange::from_usize(data) {
+                Some(range) => result_code(self.driver.set_gyro_range(range)),
+                None => ReturnCode::EINVAL,
+            },
+            6 => self.enable_fifo(data, data2, app_id),
+            7 => result_code(self.driver.disable_fifo()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+fn result_code(result: Result<(), I2cError>) -> ReturnCode {
+    match result {
+        Ok(()) => ReturnCode::SUCCESS,
+        Err(err) => err.into(),
+    }
+}
@@ -0,0 +1,136 @@
+//! Provides userspace with access to the BMP280 pressure sensor.
+//!
+//! Commands:
+//!
+//!     * 0: driver check
+//!     * 1: read pressure and temperature
+//!     * 2: set the temperature oversampling (`data`, see `bmp280::Oversampling`)
+//!     * 3: set the pressure oversampling (`data`, see `bmp280::Oversampling`)
+//!     * 4: set the IIR filter coefficient (`data`, see `bmp280::Filter`)
+//!     * 5: set the standby time in normal mode (`data`, see `bmp280::Standby`)
+//!     * 6: set the mode (`data`, 1 for forced, 3 for normal)
+//!
+//! Readings are delivered to the callback subscribed with number 0 as
+//! `callback(pressure, temperature, 0)`, with the pressure in Pa and the
+//! temperature in signed hundredths of a degree Celsius. If the sample
+//! cannot be read the callback receives `sensor::READING_FAILED` as the
+//! pressure instead.
+
+use cc26x0::bmp280::{self, Filter, Mode, Oversampling, PressureClient, Standby};
+use cc26x0::i2c::I2cError;
+use cc26x0::sensor::{self, RegisterBus};
+use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
+use kernel::hil::time::Alarm;
+
+pub const DRIVER_NUM: usize = 0x90002;
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    pending: bool,
+}
+
+pub struct PressureSensor<'a, B: RegisterBus + 'a, A: Alarm + 'a> {
+    driver: &'a bmp280::BMP280<'a, B, A>,
+    apps: Grant<App>,
+}
+
+impl<'a, B: RegisterBus + 'a, A: Alarm + 'a> PressureSensor<'a, B, A> {
+    pub fn new(driver: &'a bmp280::BMP280<'a, B, A>, grant: Grant<App>) -> PressureSensor<'a, B, A> {
+        PressureSensor {
+            driver,
+            apps: grant,
+        }
+    }
+
+    fn read(&self, app_id: AppId) -> ReturnCode {
+        let requested = self.apps.enter(app_id, |app, _| {
+            let already_pending = app.pending;
+            app.pending = true;
+            already_pending
+        });
+        match requested {
+            // A sample is already on its way
+            Ok(true) => return ReturnCode::SUCCESS,
+            Ok(false) => (),
+            Err(err) => return err.into(),
+        }
+
+        // In normal mode the sample is delivered before `read` returns, so
+        // the grant is not held across the call
+        let result = self.driver.read();
+        if result != ReturnCode::SUCCESS {
+            let _ = self.apps.enter(app_id, |app, _| app.pending = false);
+        }
+        result
+    }
+
+    fn configure<F>(&self, update: F) -> ReturnCode
+    where
+        F: FnOnce(&mut bmp280::Config) -> bool,
+    {
+        let mut config = self.driver.config();
+        if update(&mut config) {
+            self.driver.configure(config)
+        } else {
+            ReturnCode::EINVAL
+        }
+    }
+}
+
+impl<'a, B: RegisterBus + 'a, A: Alarm + 'a> PressureClient for PressureSensor<'a, B, A> {
+    fn callback(&self, sample: Result<(u32, i32), I2cError>) {
+        let (pressure, temperature) = match sample {
+            Ok((pressure, temperature)) => {
+                (pressure as usize, sensor::temperature_value(temperature))
+            }
+            Err(_) => (sensor::READING_FAILED, 0),
+        };
+
+        // Samples requested by other clients are not reported
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                if app.pending {
+                    app.pending = false;
+                    app.callback
+                        .map(|mut cb| cb.schedule(pressure, temperature, 0));
+                }
+            });
+        }
+    }
+}
+
+impl<'a, B: RegisterBus + 'a, A: Alarm + 'a> Driver for PressureSensor<'a, B, A> {
+    fn subscribe(&self, subscribe_num: usize, callback: Option<Callback>, app_id: AppId) -> ReturnCode {
+        match subscribe_num {
+            0 => self.apps
+                .enter(app_id, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn command(&self, command_num: usize, data: usize, _: usize, app_id: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => self.read(app_id),
+            2 => self.configure(|config| {
+                Oversampling::from_usize(data)
+                    .map(|o| config.temperature_oversampling = o)
+                    .is_some()
+            }),
+            3 => self.configure(|config| {
+                Oversampling::from_usize(data)
+                    .map(|o| config.pressure_oversampling = o)
+                    .is_some()
+            }),
+            4 => self.configure(|config| Filter::from_usize(data).map(|f| config.filter = f).is_some()),
+            5 => self.configure(|config| Standby::from_usize(data).map(|s| config.standby = s).is_some()),
+            6 => self.configure(|config| Mode::from_usize(data).map(|m| config.mode = m).is_some()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -1,6 +1,8 @@
 //! AUX management
 //!
 //! NOTE: as of now, the aux controller can only be used by one process at a time.
+//!
+//! The AON wake-up controller is also used to put the whole chip in shutdown.
 
 use kernel::common::VolatileCell;
 
@@ -11,7 +13,7 @@ struct AonWucRegisters {
     aux_cfg: VolatileCell<u32>,
     aux_ctl: VolatileCell<u32>,
     pwr_stat: VolatileCell<u32>,
-    _shutdown: VolatileCell<u32>,
+    shutdown: VolatileCell<u32>,
 
     _reserved0: VolatileCell<u32>,
 
@@ -70,6 +72,14 @@ pub enum AuxClock {
 
 pub const AUX_CTL: Aux = Aux::new();
 
+const AON_SYSCTL_SLEEPCTL: *const VolatileCell<u32> = 0x4009_0008 as *const VolatileCell<u32>;
+const AON_RTC_SYNC: *const VolatileCell<u32> = 0x4009_202C as *const VolatileCell<u32>;
+
+const SCB_SCR: *const VolatileCell<u32> = 0xE000_ED10 as *const VolatileCell<u32>;
+
+const SHUTDOWN_EN: u32 = 0x01;
+const SCR_SLEEPDEEP: u32 = 0x04;
+
 impl Aux {
     pub const fn new() -> Aux {
         Aux {
@@ -136,6 +146,30 @@ impl Aux {
         while self.power_status() != WakeupMode::AllowSleep { }
     }
 
+    /// Enters shutdown, the lowest power mode. Only the IO pad states are
+    /// retained, and the chip leaves shutdown through a reset or a pin
+    /// configured to wake it up, which also resets it.
+    pub fn shutdown(&self) -> ! {
+        let aon_regs: &AonWucRegisters = unsafe { &*self.aon_regs };
+        let sleepctl: &VolatileCell<u32> = unsafe { &*AON_SYSCTL_SLEEPCTL };
+        let rtc_sync: &VolatileCell<u32> = unsafe { &*AON_RTC_SYNC };
+        let scr: &VolatileCell<u32> = unsafe { &*SCB_SCR };
+
+        // Latch the pads so that the pins keep their state
+        sleepctl.set(0);
+
+        aon_regs.shutdown.set(SHUTDOWN_EN);
+
+        // Make sure the writes have reached the AON domain before sleeping
+        rtc_sync.get();
+
+        // Shutdown takes effect once the CPU enters deep sleep
+        scr.set(scr.get() | SCR_SLEEPDEEP);
+        loop {
+            wait_for_interrupt();
+        }
+    }
+
     fn wakeup_event(&self, mode: WakeupMode) {
         let aon_regs: &AonWucRegisters = unsafe { &*self.aon_regs };
         match mode {
@@ -154,3 +188,12 @@ impl Aux {
         }
     }
 }
+
+#[cfg(target_os = "none")]
+fn wait_for_interrupt() {
+    unsafe { asm!("wfi") }
+}
+
+// The unit tests run on the host, which cannot assemble `wfi`
+#[cfg(not(target_os = "none"))]
+fn wait_for_interrupt() {}
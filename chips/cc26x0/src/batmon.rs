@@ -0,0 +1,708 @@
+//! AON battery monitor (BATMON)
+//!
+//! Measures the supply voltage (VDDS) and the die temperature. Once enabled
+//! the monitor keeps measuring continuously, so a reading simply fetches
+//! the latest result. Voltages are reported in millivolts with a resolution
+//! of 1/256 V, temperatures in hundredths of a degree Celsius with a
+//! resolution of one degree.
+//!
+//! The temperature measured depends on the supply voltage, it is corrected
+//! with the slope trimmed in the factory configuration (FCFG1) the same way
+//! TI's `AONBatMonTemperatureGetDegC` does.
+//!
+//! Supply voltages can be classified into levels with `supply_level`, for
+//! policies acting as the battery runs out, and `SupplyDeadlines` keeps
+//! track of when such a policy samples and shuts down.
+//!
+//! The CC26x0 monitor has no limit comparators, so upper and lower limits
+//! are checked in software: while a limit window is enabled the latest
+//! result is compared against it at a fixed interval. Clients are notified
+//! once when a measurement leaves its window, and again only after it has
+//! come back inside.
+
+use core::cell::Cell;
+use kernel::ReturnCode;
+use kernel::common::regs::{ReadOnly, ReadWrite};
+use kernel::hil::time::{self, Alarm, Time};
+use rtc;
+use sensor;
+
+#[repr(C)]
+struct BatmonRegisters {
+    ctl: ReadWrite<u32, Control::Register>,
+    meascfg: ReadWrite<u32, MeasureConfig::Register>,
+    _reserved0: ReadOnly<u32>,
+
+    // Trimming, loaded from FCFG1 by the boot code
+    _tempp0: ReadOnly<u32>,
+    _tempp1: ReadOnly<u32>,
+    _tempp2: ReadOnly<u32>,
+    _batmonp0: ReadOnly<u32>,
+    _batmonp1: ReadOnly<u32>,
+    _iostrp0: ReadOnly<u32>,
+    _flashpumpp0: ReadOnly<u32>,
+
+    bat: ReadOnly<u32, Battery::Register>,
+    // Set when a new result is available, cleared by writing 1
+    batupd: ReadWrite<u32, Update::Register>,
+    temp: ReadOnly<u32, Temperature::Register>,
+    tempupd: ReadWrite<u32, Update::Register>,
+}
+
+register_bitfields![
+    u32,
+    Control [
+        CALC_EN OFFSET(1) NUMBITS(1) [],
+        MEAS_EN OFFSET(0) NUMBITS(1) []
+    ],
+    MeasureConfig [
+        PER OFFSET(0) NUMBITS(2) [
+            Continuous = 0b00,
+            Every8Cycles = 0b01,
+            Every16Cycles = 0b10,
+            Every32Cycles = 0b11
+        ]
+    ],
+    Battery [
+        INT OFFSET(8) NUMBITS(3) [],
+        FRAC OFFSET(0) NUMBITS(8) []
+    ],
+    Temperature [
+        INT OFFSET(8) NUMBITS(9) []
+    ],
+    Update [
+        STAT OFFSET(0) NUMBITS(1) []
+    ],
+    MiscTrim [
+        // Degrees per volt, signed with 4 fractional bits
+        TEMPVSLOPE OFFSET(0) NUMBITS(8) []
+    ]
+];
+
+const BATMON_BASE: *const BatmonRegisters = 0x4009_5000 as *const BatmonRegisters;
+const FCFG1_MISC_TRIM: *const ReadOnly<u32, MiscTrim::Register> =
+    0x5000_133C as *const ReadOnly<u32, MiscTrim::Register>;
+
+// Number of clients limit events are delivered to
+pub const BATMON_MAX_CLIENTS: usize = 2;
+
+pub const DEFAULT_POLL_INTERVAL_MS: u32 = 1000;
+pub const MIN_POLL_INTERVAL_MS: u32 = 100;
+
+// The integer part of the temperature is 9 bit two's complement
+const TEMP_INT_BITS: u32 = 9;
+// The slope correction is relative to a 3 V supply, in 1/256 V
+const TEMP_SLOPE_REFERENCE: i32 = 3 << 8;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Measurement {
+    /// Supply voltage in millivolts
+    Voltage = 0,
+    /// Die temperature in hundredths of a degree Celsius
+    Temperature = 1,
+}
+
+// Largest voltage the 3.8 bit result holds, 0x7FF
+pub const VOLTAGE_MAX_MV: i32 = 7996;
+// Range of the 9 bit two's complement temperature
+pub const TEMPERATURE_MIN: i32 = -25600;
+pub const TEMPERATURE_MAX: i32 = 25500;
+
+impl Measurement {
+    /// Lowest and highest value the monitor can report
+    pub fn range(&self) -> (i32, i32) {
+        match *self {
+            Measurement::Voltage => (0, VOLTAGE_MAX_MV),
+            Measurement::Temperature => (TEMPERATURE_MIN, TEMPERATURE_MAX),
+        }
+    }
+}
+
+// The voltage has to rise this much above a threshold to leave its level
+pub const SUPPLY_HYSTERESIS_MV: u32 = 50;
+
+#[derive(Copy, Clone, PartialEq, PartialOrd, Debug)]
+pub enum SupplyLevel {
+    Normal = 0,
+    Low = 1,
+    Critical = 2,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Limit {
+    High = 1,
+    Low = 2,
+}
+
+pub trait BatmonClient {
+    /// Called when a measurement leaves its window, with the value that
+    /// crossed the limit
+    fn limit(&self, measurement: Measurement, limit: Limit, value: i32);
+}
+
+#[derive(Copy, Clone)]
+struct Window {
+    low: i32,
+    high: i32,
+    enabled: bool,
+    // The limit last crossed, until the value is back inside
+    crossed: Option<Limit>,
+}
+
+impl Window {
+    fn new() -> Window {
+        Window {
+            low: i32::min_value(),
+            high: i32::max_value(),
+            enabled: false,
+            crossed: None,
+        }
+    }
+
+    /// Whether `low` can be the lower limit: a measurable value not above
+    /// the upper limit
+    fn accepts_low(&self, low: i32, measurement: Measurement) -> bool {
+        let (min, max) = measurement.range();
+        low >= min && low <= max && low <= self.high
+    }
+
+    /// Whether `high` can be the upper limit: a measurable value not below
+    /// the lower limit
+    fn accepts_high(&self, high: i32, measurement: Measurement) -> bool {
+        let (min, max) = measurement.range();
+        high >= min && high <= max && high >= self.low
+    }
+
+    /// Returns the limit if the value has just left the window
+    fn check(&mut self, value: i32) -> Option<Limit> {
+        let limit = if value > self.high {
+            Some(Limit::High)
+        } else if value < self.low {
+            Some(Limit::Low)
+        } else {
+            None
+        };
+
+        let changed = limit != self.crossed;
+        self.crossed = limit;
+        if changed {
+            limit
+        } else {
+            None
+        }
+    }
+}
+
+pub struct Batmon<'a, A: Alarm + 'a> {
+    regs: *const BatmonRegisters,
+    misc_trim: *const ReadOnly<u32, MiscTrim::Register>,
+    alarm: &'a A,
+    interval_ms: Cell<u32>,
+    windows: [Cell<Window>; 2],
+    clients: [Cell<Option<&'a BatmonClient>>; BATMON_MAX_CLIENTS],
+}
+
+impl<'a, A: Alarm + 'a> Batmon<'a, A> {
+    pub fn new(alarm: &'a A) -> Batmon<'a, A> {
+        Batmon {
+            regs: BATMON_BASE,
+            misc_trim: FCFG1_MISC_TRIM,
+            alarm,
+            interval_ms: Cell::new(DEFAULT_POLL_INTERVAL_MS),
+            windows: [Cell::new(Window::new()), Cell::new(Window::new())],
+            clients: [Cell::new(None), Cell::new(None)],
+        }
+    }
+
+    /// Registers a client to receive every limit event
+    pub fn add_client(&self, client: &'a BatmonClient) -> ReturnCode {
+        for slot in self.clients.iter() {
+            if slot.get().is_none() {
+                slot.set(Some(client));
+                return ReturnCode::SUCCESS;
+            }
+        }
+        ReturnCode::ENOMEM
+    }
+
+    /// Starts continuous measurement of both the voltage and the temperature
+    pub fn enable(&self) {
+        let regs: &BatmonRegisters = unsafe { &*self.regs };
+
+        // Results are only valid once the first measurement has completed
+        regs.batupd.write(Update::STAT::SET);
+        regs.tempupd.write(Update::STAT::SET);
+
+        regs.meascfg.write(MeasureConfig::PER::Continuous);
+        regs.ctl.write(Control::CALC_EN::SET + Control::MEAS_EN::SET);
+    }
+
+    pub fn disable(&self) {
+        let regs: &BatmonRegisters = unsafe { &*self.regs };
+        regs.ctl.write(Control::CALC_EN::CLEAR + Control::MEAS_EN::CLEAR);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        let regs: &BatmonRegisters = unsafe { &*self.regs };
+        regs.ctl.is_set(Control::MEAS_EN)
+    }
+
+    /// Supply voltage in millivolts, `None` until the first measurement
+    pub fn voltage(&self) -> Option<u32> {
+        let regs: &BatmonRegisters = unsafe { &*self.regs };
+        if !self.is_enabled() || !regs.batupd.is_set(Update::STAT) {
+            return None;
+        }
+        Some(convert_to_millivolts(regs.bat.get()))
+    }
+
+    /// Die temperature in hundredths of a degree Celsius, `None` until the
+    /// first measurement
+    pub fn temperature(&self) -> Option<i32> {
+        let regs: &BatmonRegisters = unsafe { &*self.regs };
+        if !self.is_enabled() || !regs.tempupd.is_set(Update::STAT) {
+            return None;
+        }
+        let misc_trim: &ReadOnly<u32, MiscTrim::Register> = unsafe { &*self.misc_trim };
+        Some(convert_to_celsius(
+            regs.temp.read(Temperature::INT),
+            regs.bat.get(),
+            misc_trim.read(MiscTrim::TEMPVSLOPE),
+        ))
+    }
+
+    pub fn read(&self, measurement: Measurement) -> Option<i32> {
+        match measurement {
+            Measurement::Voltage => self.voltage().map(|mv| mv as i32),
+            Measurement::Temperature => self.temperature(),
+        }
+    }
+
+    /// Sets the lower limit of a measurement, in millivolts or hundredths
+    /// of a degree. `EINVAL` if it can't be measured or is above the upper
+    /// limit.
+    pub fn set_low_limit(&self, measurement: Measurement, limit: i32) -> ReturnCode {
+        if !self.windows[measurement as usize].get().accepts_low(limit, measurement) {
+            return ReturnCode::EINVAL;
+        }
+        self.update_window(measurement, |window| window.low = limit);
+        ReturnCode::SUCCESS
+    }
+
+    /// Sets the upper limit of a measurement, in millivolts or hundredths
+    /// of a degree. `EINVAL` if it can't be measured or is below the lower
+    /// limit.
+    pub fn set_high_limit(&self, measurement: Measurement, limit: i32) -> ReturnCode {
+        if !self.windows[measurement as usize].get().accepts_high(limit, measurement) {
+            return ReturnCode::EINVAL;
+        }
+        self.update_window(measurement, |window| window.high = limit);
+        ReturnCode::SUCCESS
+    }
+
+    pub fn set_poll_interval(&self, interval_ms: u32) -> ReturnCode {
+        if interval_ms < MIN_POLL_INTERVAL_MS {
+            return ReturnCode::EINVAL;
+        }
+        self.interval_ms.set(interval_ms);
+        ReturnCode::SUCCESS
+    }
+
+    /// Starts checking a measurement against its window. A measurement that
+    /// is already outside is reported at the first check.
+    pub fn enable_limits(&self, measurement: Measurement) -> ReturnCode {
+        if !self.is_enabled() {
+            return ReturnCode::EOFF;
+        }
+
+        let polling = self.polling();
+        self.update_window(measurement, |window| window.enabled = true);
+
+        if !polling {
+            self.schedule_poll();
+        }
+        ReturnCode::SUCCESS
+    }
+
+    pub fn disable_limits(&self, measurement: Measurement) -> ReturnCode {
+        self.update_window(measurement, |window| window.enabled = false);
+
+        if !self.polling() {
+            self.alarm.disable();
+        }
+        ReturnCode::SUCCESS
+    }
+
+    // A changed window is checked from scratch
+    fn update_window<F>(&self, measurement: Measurement, update: F)
+    where
+        F: FnOnce(&mut Window),
+    {
+        let window = &self.windows[measurement as usize];
+        let mut updated = window.get();
+        update(&mut updated);
+        updated.crossed = None;
+        window.set(updated);
+    }
+
+    fn polling(&self) -> bool {
+        self.windows.iter().any(|window| window.get().enabled)
+    }
+
+    fn schedule_poll(&self) {
+        let ticks = rtc::ticks_from_ms::<A::Frequency>(self.interval_ms.get());
+        self.alarm.set_alarm(self.alarm.now().wrapping_add(ticks));
+    }
+
+    fn check(&self, measurement: Measurement) {
+        let window = &self.windows[measurement as usize];
+        let mut updated = window.get();
+        if !updated.enabled {
+            return;
+        }
+
+        let value = match self.read(measurement) {
+            Some(value) => value,
+            None => return,
+        };
+        let limit = updated.check(value);
+        window.set(updated);
+
+        limit.map(|limit| {
+            for slot in self.clients.iter() {
+                slot.get()
+                    .map(|client| client.limit(measurement, limit, value));
+            }
+        });
+    }
+}
+
+/// The voltage is 3 bits of integer and 8 bits of fraction
+pub fn convert_to_millivolts(raw: u32) -> u32 {
+    let raw = raw & 0x7FF;
+    (raw * 1000 + 128) >> 8
+}
+
+/// The integer part of the temperature, corrected by `slope` for the supply
+/// voltage `battery` and rounded to whole degrees
+pub fn convert_to_celsius(raw: u32, battery: u32, slope: u32) -> i32 {
+    let shift = 32 - TEMP_INT_BITS;
+    // Both in degrees with 8 fractional bits
+    let temperature = (((raw << shift) as i32) >> shift) << 8;
+    let battery = (battery & 0x7FF) as i32;
+    let correction = ((slope as u8 as i8 as i32) * (battery - TEMP_SLOPE_REFERENCE)) >> 4;
+
+    let degrees = (temperature - correction + 0x80) >> 8;
+    sensor::centi_celsius(degrees, 100, 1, 0)
+}
+
+/// Level of a supply voltage against the low and critical thresholds, in
+/// millivolts. Leaving the current level upwards takes `SUPPLY_HYSTERESIS_MV`
+/// more than its threshold, so that the voltage dips caused by radio
+/// activity do not make the level bounce.
+pub fn supply_level(voltage: u32, current: SupplyLevel, low_mv: u32, critical_mv: u32) -> SupplyLevel {
+    let above = |threshold: u32, level: SupplyLevel| {
+        if current < level {
+            voltage >= threshold
+        } else {
+            voltage >= threshold.saturating_add(SUPPLY_HYSTERESIS_MV)
+        }
+    };
+
+    if !above(critical_mv, SupplyLevel::Critical) {
+        SupplyLevel::Critical
+    } else if !above(low_mv, SupplyLevel::Low) {
+        SupplyLevel::Low
+    } else {
+        SupplyLevel::Normal
+    }
+}
+
+/// Deadlines of a policy sampling the supply voltage at an interval and
+/// shutting down once the level has stayed critical for a grace period, in
+/// alarm ticks. They are kept apart so that one alarm can serve both without
+/// a new interval moving the end of the grace period, or the grace period
+/// delaying the next sample.
+#[derive(Copy, Clone, Debug)]
+pub struct SupplyDeadlines {
+    sample: u32,
+    shutdown: Option<u32>,
+}
+
+impl SupplyDeadlines {
+    pub fn new(sample: u32) -> SupplyDeadlines {
+        SupplyDeadlines {
+            sample,
+            shutdown: None,
+        }
+    }
+
+    pub fn set_sample(&mut self, due: u32) {
+        self.sample = due;
+    }
+
+    /// Starts the grace period when the level becomes critical and cancels
+    /// it when the level has recovered
+    pub fn level_changed(&mut self, level: SupplyLevel, now: u32, grace: u32) {
+        if level != SupplyLevel::Critical {
+            self.shutdown = None;
+        } else if self.shutdown.is_none() {
+            self.shutdown = Some(now.wrapping_add(grace));
+        }
+    }
+
+    pub fn sample_expired(&self, now: u32) -> bool {
+        expired(now, self.sample)
+    }
+
+    pub fn grace_expired(&self, now: u32) -> bool {
+        self.shutdown.map_or(false, |due| expired(now, due))
+    }
+
+    /// Whether to shut down: the grace period is over and the level is
+    /// still critical
+    pub fn shutdown(&self, now: u32, level: SupplyLevel) -> bool {
+        level == SupplyLevel::Critical && self.grace_expired(now)
+    }
+
+    /// The earliest deadline, to set the alarm to
+    pub fn next(&self, now: u32) -> u32 {
+        match self.shutdown {
+            Some(due) if due.wrapping_sub(now) as i32 <= self.sample.wrapping_sub(now) as i32 => due,
+            _ => self.sample,
+        }
+    }
+}
+
+fn expired(now: u32, due: u32) -> bool {
+    now.wrapping_sub(due) as i32 >= 0
+}
+
+impl<'a, A: Alarm + 'a> time::Client for Batmon<'a, A> {
+    fn fired(&self) {
+        self.check(Measurement::Voltage);
+        self.check(Measurement::Temperature);
+
+        if self.polling() {
+            self.schedule_poll();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 3 V, where the temperature needs no correction
+    const REFERENCE: u32 = 0x300;
+
+    #[test]
+    fn voltage_is_converted_to_millivolts() {
+        assert_eq!(convert_to_millivolts(0), 0);
+        assert_eq!(convert_to_millivolts(0x300), 3000);
+        assert_eq!(convert_to_millivolts(0x380), 3500);
+        // 2.796875 V
+        assert_eq!(convert_to_millivolts(0x2CC), 2797);
+        assert_eq!(convert_to_millivolts(0x7FF), 7996);
+        // Bits above the fraction and integer part are ignored
+        assert_eq!(convert_to_millivolts(0xFFFF_F300), 3000);
+    }
+
+    #[test]
+    fn temperature_is_sign_extended() {
+        assert_eq!(convert_to_celsius(0, REFERENCE, 0), 0);
+        assert_eq!(convert_to_celsius(25, REFERENCE, 0), 2500);
+        assert_eq!(convert_to_celsius(0xFF, REFERENCE, 0), 25500);
+        assert_eq!(convert_to_celsius(0x1FF, REFERENCE, 0), -100);
+        assert_eq!(convert_to_celsius(0x1F6, REFERENCE, 0), -1000);
+        assert_eq!(convert_to_celsius(0x100, REFERENCE, 0), -25600);
+    }
+
+    #[test]
+    fn temperature_is_corrected_for_the_supply_voltage() {
+        // 1 degree per volt
+        assert_eq!(convert_to_celsius(25, REFERENCE, 0x10), 2500);
+        assert_eq!(convert_to_celsius(25, 0x400, 0x10), 2400);
+        assert_eq!(convert_to_celsius(25, 0x200, 0x10), 2600);
+        // -1 degree per volt
+        assert_eq!(convert_to_celsius(25, 0x400, 0xF0), 2600);
+        assert_eq!(convert_to_celsius(0x1F6, 0x200, 0xF0), -1100);
+        // Only the low byte holds the slope
+        assert_eq!(convert_to_celsius(25, 0x400, 0xFFFF_FF10), 2400);
+    }
+
+    #[test]
+    fn corrected_temperature_is_rounded() {
+        // 23.5 degrees
+        assert_eq!(convert_to_celsius(25, 0x400, 0x18), 2400);
+        // 24.5 degrees
+        assert_eq!(convert_to_celsius(25, 0x400, 0x08), 2500);
+        // 25.5 degrees
+        assert_eq!(convert_to_celsius(25, 0x200, 0x08), 2600);
+        // 25.25 and 24.75 degrees
+        assert_eq!(convert_to_celsius(25, 0x200, 0x04), 2500);
+        assert_eq!(convert_to_celsius(25, 0x400, 0x04), 2500);
+    }
+
+    const LOW: u32 = 2500;
+    const CRITICAL: u32 = 2200;
+
+    fn level(voltage: u32, current: SupplyLevel) -> SupplyLevel {
+        supply_level(voltage, current, LOW, CRITICAL)
+    }
+
+    #[test]
+    fn falling_voltage_crosses_the_thresholds() {
+        assert_eq!(level(3000, SupplyLevel::Normal), SupplyLevel::Normal);
+        assert_eq!(level(2500, SupplyLevel::Normal), SupplyLevel::Normal);
+        assert_eq!(level(2499, SupplyLevel::Normal), SupplyLevel::Low);
+        assert_eq!(level(2200, SupplyLevel::Low), SupplyLevel::Low);
+        assert_eq!(level(2199, SupplyLevel::Low), SupplyLevel::Critical);
+        // Falling past both thresholds at once
+        assert_eq!(level(2000, SupplyLevel::Normal), SupplyLevel::Critical);
+    }
+
+    #[test]
+    fn rising_voltage_needs_the_hysteresis() {
+        // Back at the threshold is not enough to leave the level
+        assert_eq!(level(2200, SupplyLevel::Critical), SupplyLevel::Critical);
+        assert_eq!(level(2249, SupplyLevel::Critical), SupplyLevel::Critical);
+        assert_eq!(level(2250, SupplyLevel::Critical), SupplyLevel::Low);
+
+        assert_eq!(level(2500, SupplyLevel::Low), SupplyLevel::Low);
+        assert_eq!(level(2549, SupplyLevel::Low), SupplyLevel::Low);
+        assert_eq!(level(2550, SupplyLevel::Low), SupplyLevel::Normal);
+
+        // Rising past both thresholds at once
+        assert_eq!(level(2549, SupplyLevel::Critical), SupplyLevel::Low);
+        assert_eq!(level(2550, SupplyLevel::Critical), SupplyLevel::Normal);
+    }
+
+    const INTERVAL: u32 = 1000;
+    const GRACE: u32 = 200;
+
+    #[test]
+    fn recovering_during_the_grace_period_cancels_the_shutdown() {
+        let mut deadlines = SupplyDeadlines::new(INTERVAL);
+
+        deadlines.level_changed(SupplyLevel::Critical, 100, GRACE);
+        assert_eq!(deadlines.next(100), 300);
+        deadlines.level_changed(SupplyLevel::Low, 250, GRACE);
+
+        assert!(!deadlines.grace_expired(300));
+        assert!(!deadlines.shutdown(300, SupplyLevel::Low));
+        assert_eq!(deadlines.next(300), INTERVAL);
+    }
+
+    #[test]
+    fn staying_critical_shuts_down_after_the_grace_period() {
+        let mut deadlines = SupplyDeadlines::new(INTERVAL);
+
+        deadlines.level_changed(SupplyLevel::Critical, 100, GRACE);
+        assert!(!deadlines.shutdown(299, SupplyLevel::Critical));
+        // Staying critical does not restart the grace period
+        deadlines.level_changed(SupplyLevel::Critical, 200, GRACE);
+        assert!(deadlines.shutdown(300, SupplyLevel::Critical));
+    }
+
+    #[test]
+    fn interval_and_grace_period_are_independent() {
+        let mut deadlines = SupplyDeadlines::new(INTERVAL);
+        deadlines.level_changed(SupplyLevel::Critical, 100, GRACE);
+
+        // A new interval during the grace period keeps its end
+        deadlines.set_sample(150 + 5000);
+        assert_eq!(deadlines.next(150), 300);
+        deadlines.set_sample(150 + 50);
+        assert_eq!(deadlines.next(150), 200);
+        assert!(deadlines.sample_expired(200));
+        assert!(!deadlines.grace_expired(200));
+    }
+
+    #[test]
+    fn deadlines_wrap_with_the_alarm() {
+        let now = u32::max_value() - 50;
+        let mut deadlines = SupplyDeadlines::new(now.wrapping_add(INTERVAL));
+        deadlines.level_changed(SupplyLevel::Critical, now, GRACE);
+
+        assert_eq!(deadlines.next(now), now.wrapping_add(GRACE));
+        assert!(!deadlines.grace_expired(now));
+        assert!(deadlines.grace_expired(now.wrapping_add(GRACE)));
+    }
+
+    #[test]
+    fn hysteresis_does_not_hold_a_falling_voltage() {
+        // Within the hysteresis above the threshold the level is kept
+        assert_eq!(level(2520, SupplyLevel::Normal), SupplyLevel::Normal);
+        assert_eq!(level(2220, SupplyLevel::Low), SupplyLevel::Low);
+        assert_eq!(level(2220, SupplyLevel::Critical), SupplyLevel::Critical);
+    }
+
+    fn window(low: i32, high: i32) -> Window {
+        let mut window = Window::new();
+        window.low = low;
+        window.high = high;
+        window.enabled = true;
+        window
+    }
+
+    #[test]
+    fn leaving_the_window_is_reported_once() {
+        let mut window = window(2000, 3000);
+        assert_eq!(window.check(2500), None);
+        assert_eq!(window.check(3000), None);
+        assert_eq!(window.check(3001), Some(Limit::High));
+        assert_eq!(window.check(3100), None);
+        assert_eq!(window.check(1999), Some(Limit::Low));
+        assert_eq!(window.check(1500), None);
+    }
+
+    #[test]
+    fn leaving_again_is_reported_after_reentering() {
+        let mut window = window(2000, 3000);
+        assert_eq!(window.check(3100), Some(Limit::High));
+        assert_eq!(window.check(2500), None);
+        assert_eq!(window.check(3100), Some(Limit::High));
+
+        assert_eq!(window.check(1900), Some(Limit::Low));
+        assert_eq!(window.check(2000), None);
+        assert_eq!(window.check(1900), Some(Limit::Low));
+    }
+
+    #[test]
+    fn measurable_range_matches_the_conversions() {
+        assert_eq!(convert_to_millivolts(0x7FF) as i32, VOLTAGE_MAX_MV);
+        assert_eq!(convert_to_celsius(0x100, REFERENCE, 0), TEMPERATURE_MIN);
+        assert_eq!(convert_to_celsius(0xFF, REFERENCE, 0), TEMPERATURE_MAX);
+    }
+
+    #[test]
+    fn limits_have_to_be_measurable() {
+        let window = Window::new();
+        assert!(window.accepts_low(0, Measurement::Voltage));
+        assert!(!window.accepts_low(-1, Measurement::Voltage));
+        assert!(window.accepts_high(VOLTAGE_MAX_MV, Measurement::Voltage));
+        assert!(!window.accepts_high(VOLTAGE_MAX_MV + 1, Measurement::Voltage));
+
+        assert!(window.accepts_low(TEMPERATURE_MIN, Measurement::Temperature));
+        assert!(!window.accepts_low(TEMPERATURE_MIN - 1, Measurement::Temperature));
+        assert!(window.accepts_high(TEMPERATURE_MAX, Measurement::Temperature));
+        assert!(!window.accepts_high(TEMPERATURE_MAX + 1, Measurement::Temperature));
+    }
+
+    #[test]
+    fn limits_must_not_cross() {
+        let window = window(2000, 3000);
+        assert!(window.accepts_low(3000, Measurement::Voltage));
+        assert!(!window.accepts_low(3001, Measurement::Voltage));
+        assert!(window.accepts_high(2000, Measurement::Voltage));
+        assert!(!window.accepts_high(1999, Measurement::Voltage));
+    }
+
+    #[test]
+    fn an_open_window_is_never_left() {
+        let mut window = Window::new();
+        assert_eq!(window.check(i32::min_value()), None);
+        assert_eq!(window.check(i32::max_value()), None);
+    }
+}
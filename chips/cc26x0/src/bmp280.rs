@@ -0,0 +1,629 @@
+//! BMP280 barometric pressure sensor
+//!
+//! The factory trimming parameters are read once by `init` and used to
+//! compensate every sample with the integer formulas from the Bosch
+//! datasheet (section 8.2). Pressure is reported in Pa and temperature in
+//! hundredths of a degree Celsius.
+//!
+//! In forced mode every request triggers a single measurement and the result
+//! is read back once an alarm signals the worst case measurement time has
+//! passed. In normal mode the sensor samples continuously (with the
+//! configured standby time in between) and requests return the latest result.
+
+use core::cell::Cell;
+use i2c::{I2cError, I2cInterface};
+use kernel::ReturnCode;
+use kernel::hil::time::{self, Alarm};
+use rtc;
+use sensor::{RegisterBus, Sensor};
+
+pub const BMP280_CALIB_REG: u8 = 0x88;
+pub const BMP280_ID_REG: u8 = 0xD0;
+pub const BMP280_RESET_REG: u8 = 0xE0;
+pub const BMP280_CTRL_MEAS_REG: u8 = 0xF4;
+pub const BMP280_CONFIG_REG: u8 = 0xF5;
+pub const BMP280_PRESS_REG: u8 = 0xF7;
+
+pub const BMP280_CHIP_ID: u8 = 0x58;
+pub const BMP280_RESET_VALUE: u8 = 0xB6;
+
+pub const BMP280_CALIB_LEN: usize = 24;
+// Pressure and temperature, 3 bytes each
+pub const BMP280_DATA_LEN: usize = 6;
+
+// Number of clients samples are delivered to
+pub const BMP280_MAX_CLIENTS: usize = 2;
+
+pub const BMP280_INTERFACE: I2cInterface = I2cInterface::Interface0;
+pub const BMP280_ADDRESS: u8 = 0x77;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Oversampling {
+    Skipped = 0,
+    X1 = 1,
+    X2 = 2,
+    X4 = 3,
+    X8 = 4,
+    X16 = 5,
+}
+
+impl Oversampling {
+    pub fn from_usize(value: usize) -> Option<Oversampling> {
+        match value {
+            0 => Some(Oversampling::Skipped),
+            1 => Some(Oversampling::X1),
+            2 => Some(Oversampling::X2),
+            3 => Some(Oversampling::X4),
+            4 => Some(Oversampling::X8),
+            5 => Some(Oversampling::X16),
+            _ => None,
+        }
+    }
+
+    fn samples(&self) -> u32 {
+        match *self {
+            Oversampling::Skipped => 0,
+            Oversampling::X1 => 1,
+            Oversampling::X2 => 2,
+            Oversampling::X4 => 4,
+            Oversampling::X8 => 8,
+            Oversampling::X16 => 16,
+        }
+    }
+}
+
+/// Coefficient of the IIR filter applied to the results
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Filter {
+    Off = 0,
+    X2 = 1,
+    X4 = 2,
+    X8 = 3,
+    X16 = 4,
+}
+
+impl Filter {
+    pub fn from_usize(value: usize) -> Option<Filter> {
+        match value {
+            0 => Some(Filter::Off),
+            1 => Some(Filter::X2),
+            2 => Some(Filter::X4),
+            3 => Some(Filter::X8),
+            4 => Some(Filter::X16),
+            _ => None,
+        }
+    }
+}
+
+/// Time between measurements in normal mode
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Standby {
+    Ms0_5 = 0,
+    Ms62_5 = 1,
+    Ms125 = 2,
+    Ms250 = 3,
+    Ms500 = 4,
+    Ms1000 = 5,
+    Ms2000 = 6,
+    Ms4000 = 7,
+}
+
+impl Standby {
+    pub fn from_usize(value: usize) -> Option<Standby> {
+        match value {
+            0 => Some(Standby::Ms0_5),
+            1 => Some(Standby::Ms62_5),
+            2 => Some(Standby::Ms125),
+            3 => Some(Standby::Ms250),
+            4 => Some(Standby::Ms500),
+            5 => Some(Standby::Ms1000),
+            6 => Some(Standby::Ms2000),
+            7 => Some(Standby::Ms4000),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Mode {
+    /// A single measurement per request, the sensor sleeps in between
+    Forced = 1,
+    /// Continuous measurements
+    Normal = 3,
+}
+
+impl Mode {
+    pub fn from_usize(value: usize) -> Option<Mode> {
+        match value {
+            1 => Some(Mode::Forced),
+            3 => Some(Mode::Normal),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Config {
+    pub temperature_oversampling: Oversampling,
+    pub pressure_oversampling: Oversampling,
+    pub filter: Filter,
+    pub standby: Standby,
+    pub mode: Mode,
+}
+
+impl Config {
+    /// Datasheet recommendation for weather monitoring
+    pub const fn weather_monitoring() -> Config {
+        Config {
+            temperature_oversampling: Oversampling::X1,
+            pressure_oversampling: Oversampling::X1,
+            filter: Filter::Off,
+            standby: Standby::Ms1000,
+            mode: Mode::Forced,
+        }
+    }
+
+    fn ctrl_meas(&self) -> u8 {
+        (self.temperature_oversampling as u8) << 5 | (self.pressure_oversampling as u8) << 2
+            | self.mode as u8
+    }
+
+    fn config(&self) -> u8 {
+        (self.standby as u8) << 5 | (self.filter as u8) << 2
+    }
+
+    /// Worst case measurement time from the datasheet (appendix B), in us
+    fn measurement_time_us(&self) -> u32 {
+        let mut time = 1250 + 2300 * self.temperature_oversampling.samples();
+        if self.pressure_oversampling != Oversampling::Skipped {
+            time += 2300 * self.pressure_oversampling.samples() + 575;
+        }
+        time
+    }
+}
+
+/// Factory trimming parameters
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct Calibration {
+    pub dig_t1: u16,
+    pub dig_t2: i16,
+    pub dig_t3: i16,
+    pub dig_p1: u16,
+    pub dig_p2: i16,
+    pub dig_p3: i16,
+    pub dig_p4: i16,
+    pub dig_p5: i16,
+    pub dig_p6: i16,
+    pub dig_p7: i16,
+    pub dig_p8: i16,
+    pub dig_p9: i16,
+}
+
+impl Calibration {
+    /// Parses the little endian parameters starting at register 0x88
+    pub fn from_bytes(buf: &[u8]) -> Calibration {
+        let u = |i: usize| (buf[i] as u16) | (buf[i + 1] as u16) << 8;
+        let s = |i: usize| u(i) as i16;
+
+        Calibration {
+            dig_t1: u(0),
+            dig_t2: s(2),
+            dig_t3: s(4),
+            dig_p1: u(6),
+            dig_p2: s(8),
+            dig_p3: s(10),
+            dig_p4: s(12),
+            dig_p5: s(14),
+            dig_p6: s(16),
+            dig_p7: s(18),
+            dig_p8: s(20),
+            dig_p9: s(22),
+        }
+    }
+
+    /// Returns `t_fine`, the fine temperature used for pressure compensation
+    pub fn t_fine(&self, adc_t: i32) -> i32 {
+        let t1 = self.dig_t1 as i32;
+        let var1 = (((adc_t >> 3) - (t1 << 1)) * (self.dig_t2 as i32)) >> 11;
+        let var2 = (((((adc_t >> 4) - t1) * ((adc_t >> 4) - t1)) >> 12) * (self.dig_t3 as i32)) >> 14;
+        var1 + var2
+    }
+
+    /// Temperature in hundredths of a degree Celsius
+    pub fn compensate_temperature(&self, t_fine: i32) -> i32 {
+        (t_fine * 5 + 128) >> 8
+    }
+
+    /// Pressure in Pa as unsigned 24.8 fixed point
+    pub fn compensate_pressure(&self, adc_p: i32, t_fine: i32) -> u32 {
+        let mut var1 = t_fine as i64 - 128000;
+        let mut var2 = var1 * var1 * self.dig_p6 as i64;
+        var2 = var2 + ((var1 * self.dig_p5 as i64) << 17);
+        var2 = var2 + ((self.dig_p4 as i64) << 35);
+        var1 = ((var1 * var1 * self.dig_p3 as i64) >> 8) + ((var1 * self.dig_p2 as i64) << 12);
+        var1 = ((1i64 << 47) + var1) * (self.dig_p1 as i64) >> 33;
+        if var1 == 0 {
+            // Avoid a division by zero
+            return 0;
+        }
+
+        let mut p: i64 = 1048576 - adc_p as i64;
+        p = (((p << 31) - var2) * 3125) / var1;
+        var1 = ((self.dig_p9 as i64) * (p >> 13) * (p >> 13)) >> 25;
+        var2 = ((self.dig_p8 as i64) * p) >> 19;
+        p = ((p + var1 + var2) >> 8) + ((self.dig_p7 as i64) << 4);
+        p as u32
+    }
+
+    /// Compensates raw readings into (pressure in Pa, temperature in hundredths of a degree)
+    pub fn compensate(&self, adc_p: i32, adc_t: i32) -> (u32, i32) {
+        let t_fine = self.t_fine(adc_t);
+        let pressure = self.compensate_pressure(adc_p, t_fine) >> 8;
+        (pressure, self.compensate_temperature(t_fine))
+    }
+}
+
+pub trait PressureClient {
+    /// Called with the pressure in Pa and the temperature in hundredths of a
+    /// degree, or with the error if the sample could not be read
+    fn callback(&self, sample: Result<(u32, i32), I2cError>);
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum State {
+    Idle,
+    Measuring,
+}
+
+pub struct BMP280<'a, B: RegisterBus + 'a, A: Alarm + 'a> {
+    sensor: Cell<Sensor<'a, B>>,
+    alarm: &'a A,
+    calibration: Cell<Calibration>,
+    config: Cell<Config>,
+    state: Cell<State>,
+    present: Cell<bool>,
+    clients: [Cell<Option<&'static PressureClient>>; BMP280_MAX_CLIENTS],
+}
+
+impl<'a, B: RegisterBus + 'a, A: Alarm + 'a> BMP280<'a, B, A> {
+    pub fn new(bus: &'a B, alarm: &'a A) -> BMP280<'a, B, A> {
+        BMP280 {
+            sensor: Cell::new(Sensor::new(bus, BMP280_INTERFACE, BMP280_ADDRESS)),
+            alarm,
+            calibration: Cell::new(Calibration::default()),
+            config: Cell::new(Config::weather_monitoring()),
+            state: Cell::new(State::Idle),
+            present: Cell::new(false),
+            clients: [Cell::new(None), Cell::new(None)],
+        }
+    }
+
+    /// Registers a client to receive every sample. Each client should keep
+    /// track of whether it requested the sample.
+    pub fn add_client(&self, client: &'static PressureClient) -> ReturnCode {
+        for slot in self.clients.iter() {
+            if slot.get().is_none() {
+                slot.set(Some(client));
+                return ReturnCode::SUCCESS;
+            }
+        }
+        ReturnCode::ENOMEM
+    }
+
+    /// Checks the chip id, reads the trimming parameters and applies the configuration
+    pub fn init(&self) -> ReturnCode {
+        let mut id = [0; 1];
+        if let Err(err) = self.read_registers(BMP280_ID_REG, &mut id) {
+            return err.into();
+        }
+        if id[0] != BMP280_CHIP_ID {
+            return ReturnCode::ENODEVICE;
+        }
+
+        let mut calib = [0; BMP280_CALIB_LEN];
+        if let Err(err) = self.read_registers(BMP280_CALIB_REG, &mut calib) {
+            return err.into();
+        }
+        self.calibration.set(Calibration::from_bytes(&calib));
+
+        match self.write_config() {
+            Ok(()) => {
+                self.present.set(true);
+                ReturnCode::SUCCESS
+            }
+            Err(err) => err.into(),
+        }
+    }
+
+    /// Applies new settings, refused while a forced measurement is running
+    pub fn configure(&self, config: Config) -> ReturnCode {
+        if self.state.get() == State::Measuring {
+            return ReturnCode::EBUSY;
+        }
+
+        self.config.set(config);
+        if !self.present.get() {
+            return ReturnCode::SUCCESS;
+        }
+
+        match self.write_config() {
+            Ok(()) => ReturnCode::SUCCESS,
+            Err(err) => err.into(),
+        }
+    }
+
+    pub fn config(&self) -> Config {
+        self.config.get()
+    }
+
+    /// Requests a sample, which is delivered to all clients. A request made
+    /// while a measurement is running is served by that measurement.
+    pub fn read(&self) -> ReturnCode {
+        if !self.present.get() {
+            return ReturnCode::ENODEVICE;
+        }
+        if self.state.get() == State::Measuring {
+            return ReturnCode::SUCCESS;
+        }
+
+        let config = self.config.get();
+        if config.mode == Mode::Normal {
+            return match self.read_sample() {
+                Ok(sample) => {
+                    self.deliver(Ok(sample));
+                    ReturnCode::SUCCESS
+                }
+                Err(err) => err.into(),
+            };
+        }
+
+        // Writing ctrl_meas in forced mode starts a single measurement
+        if let Err(err) = self.write_register(BMP280_CTRL_MEAS_REG, config.ctrl_meas()) {
+            return err.into();
+        }
+
+        self.state.set(State::Measuring);
+        let ticks = rtc::ticks_from_us::<A::Frequency>(config.measurement_time_us());
+        self.alarm.set_alarm(self.alarm.now().wrapping_add(ticks));
+        ReturnCode::SUCCESS
+    }
+
+    fn read_sample(&self) -> Result<(u32, i32), I2cError> {
+        let mut buf = [0; BMP280_DATA_LEN];
+        self.read_registers(BMP280_PRESS_REG, &mut buf)?;
+
+        let adc_p = (buf[0] as i32) << 12 | (buf[1] as i32) << 4 | (buf[2] as i32) >> 4;
+        let adc_t = (buf[3] as i32) << 12 | (buf[4] as i32) << 4 | (buf[5] as i32) >> 4;
+        Ok(self.calibration.get().compensate(adc_p, adc_t))
+    }
+
+    fn deliver(&self, sample: Result<(u32, i32), I2cError>) {
+        for slot in self.clients.iter() {
+            slot.get().map(|client| client.callback(sample));
+        }
+    }
+
+    fn write_config(&self) -> Result<(), I2cError> {
+        let config = self.config.get();
+        // The config register is only guaranteed to be written in sleep mode
+        self.write_register(BMP280_CTRL_MEAS_REG, config.ctrl_meas() & !0x3)?;
+        self.write_register(BMP280_CONFIG_REG, config.config())?;
+        if config.mode == Mode::Normal {
+            self.write_register(BMP280_CTRL_MEAS_REG, config.ctrl_meas())?;
+        }
+        Ok(())
+    }
+
+    fn read_registers(&self, reg: u8, buf: &mut [u8]) -> Result<(), I2cError> {
+        let sensor = self.sensor.get();
+        let len = buf.len() as u8;
+
+        sensor.select();
+        let result = sensor.read_from_reg(reg, buf, len);
+        sensor.deselect();
+        result
+    }
+
+    fn write_register(&self, reg: u8, value: u8) -> Result<(), I2cError> {
+        let sensor = self.sensor.get();
+
+        sensor.select();
+        let result = sensor.write_to_reg(reg, &[value]);
+        sensor.deselect();
+        result
+    }
+}
+
+impl<'a, B: RegisterBus + 'a, A: Alarm + 'a> time::Client for BMP280<'a, B, A> {
+    fn fired(&self) {
+        self.state.set(State::Idle);
+
+        // The clients are waiting for the measurement, so a failed readout
+        // is reported to them as well
+        let sample = self.read_sample();
+        self.deliver(sample);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kernel::hil::time::{Client, Time};
+    use sim_bus::{SimulatedAlarm, SimulatedBus};
+
+    // Example from the datasheet (section 8.2)
+    const DATASHEET_CALIBRATION: Calibration = Calibration {
+        dig_t1: 27504,
+        dig_t2: 26435,
+        dig_t3: -1000,
+        dig_p1: 36477,
+        dig_p2: -10685,
+        dig_p3: 3024,
+        dig_p4: 2855,
+        dig_p5: 140,
+        dig_p6: -7,
+        dig_p7: 15500,
+        dig_p8: -14600,
+        dig_p9: 6000,
+    };
+    const DATASHEET_ADC_T: i32 = 519888;
+    const DATASHEET_ADC_P: i32 = 415148;
+
+    struct Samples {
+        last: Cell<Option<Result<(u32, i32), I2cError>>>,
+    }
+
+    impl PressureClient for Samples {
+        fn callback(&self, sample: Result<(u32, i32), I2cError>) {
+            self.last.set(Some(sample));
+        }
+    }
+
+    fn calibration_bytes(calib: &Calibration) -> [u8; BMP280_CALIB_LEN] {
+        let words = [
+            calib.dig_t1,
+            calib.dig_t2 as u16,
+            calib.dig_t3 as u16,
+            calib.dig_p1,
+            calib.dig_p2 as u16,
+            calib.dig_p3 as u16,
+            calib.dig_p4 as u16,
+            calib.dig_p5 as u16,
+            calib.dig_p6 as u16,
+            calib.dig_p7 as u16,
+            calib.dig_p8 as u16,
+            calib.dig_p9 as u16,
+        ];
+        let mut bytes = [0; BMP280_CALIB_LEN];
+        for (i, word) in words.iter().enumerate() {
+            bytes[2 * i] = *word as u8;
+            bytes[2 * i + 1] = (*word >> 8) as u8;
+        }
+        bytes
+    }
+
+    // A device holding the datasheet trimming parameters and sample
+    fn device() -> SimulatedBus {
+        let bus = SimulatedBus::new(BMP280_ADDRESS, 1);
+        bus.set_register(BMP280_ID_REG, BMP280_CHIP_ID as u16);
+        for (i, byte) in calibration_bytes(&DATASHEET_CALIBRATION).iter().enumerate() {
+            bus.set_register(BMP280_CALIB_REG + i as u8, *byte as u16);
+        }
+
+        let raw = [DATASHEET_ADC_P, DATASHEET_ADC_T];
+        for (i, adc) in raw.iter().enumerate() {
+            let reg = BMP280_PRESS_REG + 3 * i as u8;
+            bus.set_register(reg, (adc >> 12) as u16 & 0xFF);
+            bus.set_register(reg + 1, (adc >> 4) as u16 & 0xFF);
+            bus.set_register(reg + 2, (adc << 4) as u16 & 0xF0);
+        }
+        bus
+    }
+
+    fn subscribe(bmp: &BMP280<SimulatedBus, SimulatedAlarm>) -> &'static Samples {
+        let samples: &'static Samples = Box::leak(Box::new(Samples {
+            last: Cell::new(None),
+        }));
+        assert_eq!(bmp.add_client(samples), ReturnCode::SUCCESS);
+        samples
+    }
+
+    #[test]
+    fn datasheet_example() {
+        let calib = DATASHEET_CALIBRATION;
+        let t_fine = calib.t_fine(DATASHEET_ADC_T);
+        assert_eq!(t_fine, 128422);
+        assert_eq!(calib.compensate_temperature(t_fine), 2508);
+        // 100653.25 Pa in 24.8 fixed point, the datasheet gives 100653.27 Pa
+        // for the floating point compensation
+        assert_eq!(calib.compensate_pressure(DATASHEET_ADC_P, t_fine), 25767233);
+        assert_eq!(calib.compensate(DATASHEET_ADC_P, DATASHEET_ADC_T), (100653, 2508));
+    }
+
+    #[test]
+    fn calibration_is_little_endian() {
+        let bytes = calibration_bytes(&DATASHEET_CALIBRATION);
+        assert_eq!(bytes[0], 0x70);
+        assert_eq!(bytes[1], 0x6B);
+        assert_eq!(Calibration::from_bytes(&bytes), DATASHEET_CALIBRATION);
+    }
+
+    #[test]
+    fn uncalibrated_pressure_does_not_divide_by_zero() {
+        let calib = Calibration::default();
+        assert_eq!(calib.compensate_pressure(DATASHEET_ADC_P, 0), 0);
+    }
+
+    #[test]
+    fn forced_measurement() {
+        let bus = device();
+        let alarm = SimulatedAlarm::new();
+        let bmp = BMP280::new(&bus, &alarm);
+        assert_eq!(bmp.init(), ReturnCode::SUCCESS);
+        let samples = subscribe(&bmp);
+
+        assert_eq!(bmp.read(), ReturnCode::SUCCESS);
+        assert_eq!(
+            bus.register(BMP280_CTRL_MEAS_REG) as u8,
+            Config::weather_monitoring().ctrl_meas()
+        );
+        assert_eq!(samples.last.get(), None);
+
+        assert!(alarm.expire());
+        bmp.fired();
+        assert_eq!(samples.last.get(), Some(Ok((100653, 2508))));
+    }
+
+    #[test]
+    fn normal_mode_reads_the_latest_result() {
+        let bus = device();
+        let alarm = SimulatedAlarm::new();
+        let bmp = BMP280::new(&bus, &alarm);
+        assert_eq!(bmp.init(), ReturnCode::SUCCESS);
+        let samples = subscribe(&bmp);
+
+        let mut config = bmp.config();
+        config.mode = Mode::Normal;
+        assert_eq!(bmp.configure(config), ReturnCode::SUCCESS);
+
+        assert_eq!(bmp.read(), ReturnCode::SUCCESS);
+        assert!(!alarm.is_armed());
+        assert_eq!(samples.last.get(), Some(Ok((100653, 2508))));
+
+        // A failed read is returned to the caller only
+        samples.last.set(None);
+        bus.fail_next(I2cError::DataNack);
+        assert_eq!(bmp.read(), ReturnCode::ENOACK);
+        assert_eq!(samples.last.get(), None);
+    }
+
+    #[test]
+    fn failed_readout_is_reported() {
+        let bus = device();
+        let alarm = SimulatedAlarm::new();
+        let bmp = BMP280::new(&bus, &alarm);
+        assert_eq!(bmp.init(), ReturnCode::SUCCESS);
+        let samples = subscribe(&bmp);
+
+        assert_eq!(bmp.read(), ReturnCode::SUCCESS);
+        bus.fail_next(I2cError::DataNack);
+        assert!(alarm.expire());
+        bmp.fired();
+        assert_eq!(samples.last.get(), Some(Err(I2cError::DataNack)));
+
+        // The next request starts a new measurement
+        assert_eq!(bmp.read(), ReturnCode::SUCCESS);
+        assert!(alarm.is_armed());
+    }
+
+    #[test]
+    fn init_rejects_other_devices() {
+        let bus = device();
+        bus.set_register(BMP280_ID_REG, 0x60);
+        let alarm = SimulatedAlarm::new();
+        let bmp = BMP280::new(&bus, &alarm);
+
+        assert_eq!(bmp.init(), ReturnCode::ENODEVICE);
+        assert_eq!(bmp.read(), ReturnCode::ENODEVICE);
+    }
+}
@@ -1,11 +1,13 @@
 use cortexm3::{self, nvic};
-use cc26xx::gpio;
 use cc26xx::peripheral_interrupts::*;
 
 const X0_RF_CPE1: u32 = 2;
 const X0_RF_CPE0: u32 = 9;
 const X0_RF_CMD_ACK: u32 = 11;
+const X0_I2C0: u32 = 1;
 
+use gpio;
+use i2c;
 use radio;
 use timer;
 use uart;
@@ -48,6 +50,8 @@ impl kernel::Chip for Cc26x0 {
 
                     UART0 => uart::UART0.handle_interrupt(),
 
+                    X0_I2C0 => i2c::I2C0.handle_interrupt(),
+
                     GPT0A => timer::GPT0.handle_interrupt(),
                     GPT0B => timer::GPT0.handle_interrupt(),
                     GPT1A => timer::GPT1.handle_interrupt(),
@@ -169,12 +169,13 @@ impl Port {
 
         // evflags indicate which pins has triggered an interrupt,
         // we need to call the respective handler for positive bit in evflags.
-        let mut pin: usize = usize::max_value();
-        while pin < self.pins.len() {
-            pin = evflags.trailing_zeros() as usize;
+        let mut pending = evflags;
+        while pending != 0 {
+            let pin = pending.trailing_zeros() as usize;
             if pin >= self.pins.len() {
                 break;
             }
+            pending &= !(1 << pin);
 
             self.pins[pin].handle_interrupt();
         }
@@ -1,69 +1,614 @@
-use i2c::I2cInterface;
+//! HDC1000 temperature and humidity sensor
+//!
+//! The sensor is configured to acquire temperature and humidity in sequence,
+//! so a single conversion serves both a temperature and a humidity request.
+//! A conversion is started by pointing to the temperature register, after
+//! which we wait on an alarm until the results are ready to be read.
+//!
+//! `init` must be called before use: it verifies the identification
+//! registers so that a missing or unexpected device is reported.
+//! Resolution and heater settings are applied with the next conversion.
+//!
+//! If the results cannot be read back the conversion is restarted a few
+//! times, after which the clients receive `sensor::READING_FAILED`.
+
+use i2c::{I2cError, I2cInterface};
 use core::cell::Cell;
-use sensor::Sensor;
+use sensor::{self, RegisterBus, Sensor};
 use kernel;
+use kernel::hil::sensors::{HumidityClient, HumidityDriver, TemperatureClient, TemperatureDriver};
+use kernel::hil::time::{self, Alarm};
+use rtc;
 
 pub const HDC_TEMP_REG: u32 = 0x00;
 pub const HDC_CONF_REG: u32 = 0x02;
+pub const HDC_MANUFACTURER_ID_REG: u32 = 0xFE;
+pub const HDC_DEVICE_ID_REG: u32 = 0xFF;
+
+pub const HDC_MANUFACTURER_ID: u16 = 0x5449; // Texas Instruments
+pub const HDC_DEVICE_ID: u16 = 0x1000;
+
+pub const HDC_CONFIG_HEAT: u16 = 1 << 13;
+pub const HDC_CONFIG_MODE: u16 = 1 << 12; // Acquire both values in sequence
+pub const HDC_CONFIG_BTST: u16 = 1 << 11;
+pub const HDC_CONFIG_TRES_11BIT: u16 = 1 << 10;
+pub const HDC_CONFIG_HRES_11BIT: u16 = 0b01 << 8;
+pub const HDC_CONFIG_HRES_8BIT: u16 = 0b10 << 8;
 
-pub const HDC_CONFIG: u32 = 0x1000; // 14 bit resolution
+// Margin added to the conversion times from the datasheet
+pub const HDC_CONVERSION_MARGIN_US: u32 = 1_000;
+
+// Number of times a failed readout restarts the conversion before giving up
+pub const HDC_MAX_RETRIES: u8 = 3;
 
 pub const HDC_INTERFACE: I2cInterface = I2cInterface::Interface0;
 pub const HDC_ADDRESS: u8 = 0x43;
 
-pub struct HDC {
-    sensor: Cell<Sensor>,
-    client: Cell<Option<&'static kernel::hil::sensors::TemperatureClient>>,
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum TemperatureResolution {
+    Bits11,
+    Bits14,
 }
 
-impl HDC {
-    pub unsafe fn new() -> HDC {
+impl TemperatureResolution {
+    fn config_bits(&self) -> u16 {
+        match *self {
+            TemperatureResolution::Bits11 => HDC_CONFIG_TRES_11BIT,
+            TemperatureResolution::Bits14 => 0,
+        }
+    }
+
+    fn conversion_time_us(&self) -> u32 {
+        match *self {
+            TemperatureResolution::Bits11 => 3_650,
+            TemperatureResolution::Bits14 => 6_350,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum HumidityResolution {
+    Bits8,
+    Bits11,
+    Bits14,
+}
+
+impl HumidityResolution {
+    fn config_bits(&self) -> u16 {
+        match *self {
+            HumidityResolution::Bits8 => HDC_CONFIG_HRES_8BIT,
+            HumidityResolution::Bits11 => HDC_CONFIG_HRES_11BIT,
+            HumidityResolution::Bits14 => 0,
+        }
+    }
+
+    fn conversion_time_us(&self) -> u32 {
+        match *self {
+            HumidityResolution::Bits8 => 2_500,
+            HumidityResolution::Bits11 => 3_850,
+            HumidityResolution::Bits14 => 6_500,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum State {
+    Idle,
+    Converting,
+}
+
+pub struct HDC<'a, B: RegisterBus + 'a, A: Alarm + 'a> {
+    sensor: Cell<Sensor<'a, B>>,
+    alarm: &'a A,
+    state: Cell<State>,
+    present: Cell<bool>,
+    temperature_resolution: Cell<TemperatureResolution>,
+    humidity_resolution: Cell<HumidityResolution>,
+    heater: Cell<bool>,
+    retries: Cell<u8>,
+    temperature_pending: Cell<bool>,
+    humidity_pending: Cell<bool>,
+    temperature_client: Cell<Option<&'static TemperatureClient>>,
+    humidity_client: Cell<Option<&'static HumidityClient>>,
+}
+
+impl<'a, B: RegisterBus + 'a, A: Alarm + 'a> HDC<'a, B, A> {
+    pub fn new(bus: &'a B, alarm: &'a A) -> HDC<'a, B, A> {
         HDC {
-            sensor: Cell::new(Sensor::new(HDC_INTERFACE, HDC_ADDRESS)),
-            client: Cell::new(None),
+            sensor: Cell::new(Sensor::new(bus, HDC_INTERFACE, HDC_ADDRESS)),
+            alarm,
+            state: Cell::new(State::Idle),
+            present: Cell::new(false),
+            temperature_resolution: Cell::new(TemperatureResolution::Bits14),
+            humidity_resolution: Cell::new(HumidityResolution::Bits14),
+            heater: Cell::new(false),
+            retries: Cell::new(0),
+            temperature_pending: Cell::new(false),
+            humidity_pending: Cell::new(false),
+            temperature_client: Cell::new(None),
+            humidity_client: Cell::new(None),
+        }
+    }
+
+    /// Checks the identification registers and writes the configuration.
+    /// Readings are refused with `ENODEVICE` until this succeeds.
+    pub fn init(&self) -> kernel::ReturnCode {
+        let sensor = self.sensor.get();
+        sensor.select();
+        let result = self.identify(&sensor).and_then(|present| {
+            if present {
+                self.write_config(&sensor).map(|_| true)
+            } else {
+                Ok(false)
+            }
+        });
+        sensor.deselect();
+
+        match result {
+            Ok(present) => {
+                self.present.set(present);
+                if present {
+                    kernel::ReturnCode::SUCCESS
+                } else {
+                    kernel::ReturnCode::ENODEVICE
+                }
+            }
+            Err(err) => {
+                self.present.set(false);
+                err.into()
+            }
+        }
+    }
+
+    pub fn set_temperature_resolution(&self, resolution: TemperatureResolution) {
+        self.temperature_resolution.set(resolution);
+    }
+
+    pub fn set_humidity_resolution(&self, resolution: HumidityResolution) {
+        self.humidity_resolution.set(resolution);
+    }
+
+    /// Enables the on-chip heater, which is active while converting and can
+    /// be used to drive off condensation.
+    pub fn set_heater(&self, enable: bool) {
+        self.heater.set(enable);
+    }
+
+    /// Returns true if the supply voltage is below 2.8V
+    pub fn battery_low(&self) -> Result<bool, I2cError> {
+        let config = self.read_register(HDC_CONF_REG as u8)?;
+        Ok(config & HDC_CONFIG_BTST != 0)
+    }
+
+    fn config(&self) -> u16 {
+        let mut config = HDC_CONFIG_MODE | self.temperature_resolution.get().config_bits()
+            | self.humidity_resolution.get().config_bits();
+        if self.heater.get() {
+            config |= HDC_CONFIG_HEAT;
         }
+        config
+    }
+
+    fn conversion_time_us(&self) -> u32 {
+        self.temperature_resolution.get().conversion_time_us()
+            + self.humidity_resolution.get().conversion_time_us()
+            + HDC_CONVERSION_MARGIN_US
+    }
+
+    fn identify(&self, sensor: &Sensor<'a, B>) -> Result<bool, I2cError> {
+        let mut buf = [0; 2];
+
+        sensor.read_from_reg(HDC_MANUFACTURER_ID_REG as u8, &mut buf, 2)?;
+        let manufacturer = (buf[0] as u16) << 8 | (buf[1] as u16);
+
+        sensor.read_from_reg(HDC_DEVICE_ID_REG as u8, &mut buf, 2)?;
+        let device = (buf[0] as u16) << 8 | (buf[1] as u16);
+
+        Ok(manufacturer == HDC_MANUFACTURER_ID && device == HDC_DEVICE_ID)
     }
 
-    pub unsafe fn read_temp(&self) -> u32 {
-        self.sensor.get().select();
+    fn read_register(&self, reg: u8) -> Result<u16, I2cError> {
+        let sensor = self.sensor.get();
         let mut buf = [0; 2];
 
+        sensor.select();
+        let result = sensor.read_from_reg(reg, &mut buf, 2);
+        sensor.deselect();
+        result?;
+
+        Ok((buf[0] as u16) << 8 | (buf[1] as u16))
+    }
+
+    fn write_config(&self, sensor: &Sensor<'a, B>) -> Result<(), I2cError> {
+        let config = self.config();
+        let buf = [(config >> 8) as u8, config as u8];
+        sensor.write_to_reg(HDC_CONF_REG as u8, &buf)
+    }
+
+    /// Starts a conversion unless one is already running
+    fn request(&self) -> kernel::ReturnCode {
+        if !self.present.get() {
+            self.temperature_pending.set(false);
+            self.humidity_pending.set(false);
+            return kernel::ReturnCode::ENODEVICE;
+        }
+
+        if self.state.get() == State::Converting {
+            return kernel::ReturnCode::SUCCESS;
+        }
+
+        self.retries.set(0);
+        match self.start_conversion() {
+            Ok(()) => kernel::ReturnCode::SUCCESS,
+            Err(err) => {
+                self.temperature_pending.set(false);
+                self.humidity_pending.set(false);
+                err.into()
+            }
+        }
+    }
+
+    fn start_conversion(&self) -> Result<(), I2cError> {
+        let sensor = self.sensor.get();
+        sensor.select();
+        let result = self.trigger(&sensor);
+        sensor.deselect();
+        result?;
+
+        self.state.set(State::Converting);
+
+        let ticks = rtc::ticks_from_us::<A::Frequency>(self.conversion_time_us());
+        self.alarm.set_alarm(self.alarm.now().wrapping_add(ticks));
+        Ok(())
+    }
+
+    fn trigger(&self, sensor: &Sensor<'a, B>) -> Result<(), I2cError> {
         // Write config to peripheral
-        buf[0] = ((HDC_CONFIG & 0xFF00) >> 8) as u8;
-        buf[1] = (HDC_CONFIG & 0xFF) as u8;
-        self.sensor.get().write_to_reg(HDC_CONF_REG as u8, &mut buf, 2);
+        self.write_config(sensor)?;
 
         // Start measurement by selecting temperature register
-        self.sensor.get().write_reg_address(HDC_TEMP_REG as u8);
+        sensor.write_reg_address(HDC_TEMP_REG as u8)
+    }
 
-        // Delay to make sure the value is ready when reading
-        for _ in 0..0xFFFFFF { asm!("NOP"); }
+    // Reads temperature and humidity, in that order
+    fn read_results(&self) -> Result<(u16, u16), I2cError> {
+        let sensor = self.sensor.get();
+        let mut buf = [0; 4];
 
-        // Read the temperature
-        self.sensor.get().read(&mut buf, 2);
+        sensor.select();
+        let result = sensor.read(&mut buf, 4);
+        sensor.deselect();
+        result?;
 
-        let raw_temp = (buf[0] as u32) << 8 | (buf[1] as u32);
-        self.convert_to_celsius(raw_temp)
+        let raw_temp = (buf[0] as u16) << 8 | (buf[1] as u16);
+        let raw_humidity = (buf[2] as u16) << 8 | (buf[3] as u16);
+        Ok((raw_temp, raw_humidity))
     }
 
-    fn convert_to_celsius(&self, raw_temp: u32) -> u32 {
-        raw_temp * 165 / 65536 - 40
+    fn fail_pending(&self) {
+        if self.temperature_pending.get() {
+            self.temperature_pending.set(false);
+            self.temperature_client
+                .get()
+                .map(|client| client.callback(sensor::READING_FAILED));
+        }
+
+        if self.humidity_pending.get() {
+            self.humidity_pending.set(false);
+            self.humidity_client
+                .get()
+                .map(|client| client.callback(sensor::READING_FAILED));
+        }
     }
 }
 
-impl kernel::hil::sensors::TemperatureDriver for HDC {
+/// Temperature in hundredths of a degree Celsius: raw / 2^16 * 165 - 40
+pub fn convert_to_celsius(raw_temp: u16) -> i32 {
+    sensor::centi_celsius(raw_temp as i32, 16500, 65536, -4000)
+}
+
+/// Relative humidity in hundredths of a percent: raw / 2^16 * 100
+pub fn convert_to_humidity(raw_humidity: u16) -> u32 {
+    raw_humidity as u32 * 10000 / 65536
+}
+
+impl<'a, B: RegisterBus + 'a, A: Alarm + 'a> time::Client for HDC<'a, B, A> {
+    fn fired(&self) {
+        self.state.set(State::Idle);
+
+        match self.read_results() {
+            Ok((raw_temp, raw_humidity)) => {
+                if self.temperature_pending.get() {
+                    self.temperature_pending.set(false);
+                    let temp = convert_to_celsius(raw_temp);
+                    self.temperature_client
+                        .get()
+                        .map(|client| client.callback(sensor::temperature_value(temp)));
+                }
+
+                if self.humidity_pending.get() {
+                    self.humidity_pending.set(false);
+                    let humidity = convert_to_humidity(raw_humidity);
+                    self.humidity_client
+                        .get()
+                        .map(|client| client.callback(humidity as usize));
+                }
+            }
+            Err(_) => {
+                let retries = self.retries.get() + 1;
+                self.retries.set(retries);
+                if retries > HDC_MAX_RETRIES || self.start_conversion().is_err() {
+                    self.fail_pending();
+                }
+            }
+        }
+    }
+}
+
+impl<'a, B: RegisterBus + 'a, A: Alarm + 'a> TemperatureDriver for HDC<'a, B, A> {
     fn read_temperature(&self) -> kernel::ReturnCode {
-        unsafe {
-            let temp = self.read_temp();
-            self.client
-                .get()
-                .map(|client| client.callback(temp as usize));
-            self.sensor.get().deselect();
+        self.temperature_pending.set(true);
+        self.request()
+    }
+
+    fn set_client(&self, client: &'static TemperatureClient) {
+        self.temperature_client.set(Some(client));
+    }
+}
+
+impl<'a, B: RegisterBus + 'a, A: Alarm + 'a> HumidityDriver for HDC<'a, B, A> {
+    fn read_humidity(&self) -> kernel::ReturnCode {
+        self.humidity_pending.set(true);
+        self.request()
+    }
+
+    fn set_client(&self, client: &'static HumidityClient) {
+        self.humidity_client.set(Some(client));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+    use kernel::ReturnCode;
+    use kernel::hil::time::{Client, Time};
+    use sim_bus::{SimulatedAlarm, SimulatedBus};
+
+    // The humidity register follows the temperature register
+    const HDC_HUMIDITY_REG: u8 = HDC_TEMP_REG as u8 + 1;
+
+    struct Readings {
+        temperature: Cell<Option<usize>>,
+        humidity: Cell<Option<usize>>,
+    }
+
+    impl TemperatureClient for Readings {
+        fn callback(&self, value: usize) {
+            self.temperature.set(Some(value));
+        }
+    }
+
+    impl HumidityClient for Readings {
+        fn callback(&self, value: usize) {
+            self.humidity.set(Some(value));
+        }
+    }
+
+    fn device() -> SimulatedBus {
+        let bus = SimulatedBus::new(HDC_ADDRESS, 2);
+        bus.set_register(HDC_MANUFACTURER_ID_REG as u8, HDC_MANUFACTURER_ID);
+        bus.set_register(HDC_DEVICE_ID_REG as u8, HDC_DEVICE_ID);
+        bus
+    }
+
+    fn subscribe(hdc: &HDC<SimulatedBus, SimulatedAlarm>) -> &'static Readings {
+        let readings: &'static Readings = Box::leak(Box::new(Readings {
+            temperature: Cell::new(None),
+            humidity: Cell::new(None),
+        }));
+        TemperatureDriver::set_client(hdc, readings);
+        HumidityDriver::set_client(hdc, readings);
+        readings
+    }
+
+    // Lets the conversion in progress complete
+    fn convert(hdc: &HDC<SimulatedBus, SimulatedAlarm>, alarm: &SimulatedAlarm) {
+        assert!(alarm.expire());
+        hdc.fired();
+    }
+
+    #[test]
+    fn temperature_conversion_range() {
+        assert_eq!(convert_to_celsius(0), -4000);
+        assert_eq!(convert_to_celsius(0x8000), 4250);
+        assert_eq!(convert_to_celsius(0xFFFF), 12500);
+    }
+
+    #[test]
+    fn temperature_conversion_is_monotonic() {
+        let mut previous = convert_to_celsius(0);
+        for raw in 1..=0xFFFF {
+            let temp = convert_to_celsius(raw);
+            assert!(temp >= previous, "raw {:#x}: {} < {}", raw, temp, previous);
+            // A step of the raw value is a fraction of a hundredth
+            assert!(temp - previous <= 1);
+            previous = temp;
         }
-        kernel::ReturnCode::SUCCESS
     }
 
-    fn set_client(&self, client: &'static kernel::hil::sensors::TemperatureClient) {
-        self.client.set(Some(client));
+    #[test]
+    fn temperature_conversion_below_zero() {
+        // -10.00 C
+        assert_eq!(convert_to_celsius(11916), -1000);
+        // Just below and at zero
+        assert_eq!(convert_to_celsius(15885), -1);
+        assert_eq!(convert_to_celsius(15886), 0);
+        assert_eq!(sensor::temperature_value(convert_to_celsius(11916)) as isize, -1000);
+    }
+
+    #[test]
+    fn init_writes_the_configuration() {
+        let bus = device();
+        let alarm = SimulatedAlarm::new();
+        let hdc = HDC::new(&bus, &alarm);
+
+        assert_eq!(hdc.init(), ReturnCode::SUCCESS);
+        assert_eq!(bus.register(HDC_CONF_REG as u8), HDC_CONFIG_MODE);
+    }
+
+    #[test]
+    fn init_rejects_other_devices() {
+        let bus = device();
+        bus.set_register(HDC_DEVICE_ID_REG as u8, 0x1050);
+        let alarm = SimulatedAlarm::new();
+        let hdc = HDC::new(&bus, &alarm);
+
+        assert_eq!(hdc.init(), ReturnCode::ENODEVICE);
+        assert_eq!(bus.register(HDC_CONF_REG as u8), 0);
+        assert_eq!(hdc.read_temperature(), ReturnCode::ENODEVICE);
+        assert!(!alarm.is_armed());
+    }
+
+    #[test]
+    fn init_reports_a_missing_device() {
+        let bus = SimulatedBus::new(HDC_ADDRESS + 1, 2);
+        let alarm = SimulatedAlarm::new();
+        let hdc = HDC::new(&bus, &alarm);
+
+        assert_eq!(hdc.init(), ReturnCode::ENODEVICE);
+        assert_eq!(hdc.read_humidity(), ReturnCode::ENODEVICE);
+    }
+
+    #[test]
+    fn init_reports_bus_errors() {
+        let bus = device();
+        bus.fail_next(I2cError::DataNack);
+        let alarm = SimulatedAlarm::new();
+        let hdc = HDC::new(&bus, &alarm);
+
+        assert_eq!(hdc.init(), ReturnCode::ENOACK);
+        assert_eq!(hdc.read_temperature(), ReturnCode::ENODEVICE);
+    }
+
+    #[test]
+    fn one_conversion_serves_both_readings() {
+        let bus = device();
+        let alarm = SimulatedAlarm::new();
+        let hdc = HDC::new(&bus, &alarm);
+        assert_eq!(hdc.init(), ReturnCode::SUCCESS);
+        let readings = subscribe(&hdc);
+
+        bus.set_register(HDC_TEMP_REG as u8, 0x8000);
+        bus.set_register(HDC_HUMIDITY_REG, 0x4000);
+
+        assert_eq!(hdc.read_temperature(), ReturnCode::SUCCESS);
+        assert_eq!(hdc.read_humidity(), ReturnCode::SUCCESS);
+        // The conversion is started by pointing to the temperature register
+        assert_eq!(bus.pointer(), HDC_TEMP_REG as u8);
+        assert_eq!(readings.temperature.get(), None);
+
+        convert(&hdc, &alarm);
+        assert_eq!(readings.temperature.get(), Some(4250));
+        assert_eq!(readings.humidity.get(), Some(2500));
+        assert!(!alarm.is_armed());
+    }
+
+    #[test]
+    fn only_requested_readings_are_delivered() {
+        let bus = device();
+        let alarm = SimulatedAlarm::new();
+        let hdc = HDC::new(&bus, &alarm);
+        assert_eq!(hdc.init(), ReturnCode::SUCCESS);
+        let readings = subscribe(&hdc);
+
+        assert_eq!(hdc.read_humidity(), ReturnCode::SUCCESS);
+        convert(&hdc, &alarm);
+        assert_eq!(readings.temperature.get(), None);
+        assert_eq!(readings.humidity.get(), Some(0));
+    }
+
+    #[test]
+    fn settings_are_written_with_the_next_conversion() {
+        let bus = device();
+        let alarm = SimulatedAlarm::new();
+        let hdc = HDC::new(&bus, &alarm);
+        assert_eq!(hdc.init(), ReturnCode::SUCCESS);
+
+        hdc.set_heater(true);
+        hdc.set_temperature_resolution(TemperatureResolution::Bits11);
+        hdc.set_humidity_resolution(HumidityResolution::Bits8);
+        assert_eq!(bus.register(HDC_CONF_REG as u8), HDC_CONFIG_MODE);
+
+        assert_eq!(hdc.read_temperature(), ReturnCode::SUCCESS);
+        assert_eq!(
+            bus.register(HDC_CONF_REG as u8),
+            HDC_CONFIG_MODE | HDC_CONFIG_HEAT | HDC_CONFIG_TRES_11BIT | HDC_CONFIG_HRES_8BIT
+        );
+    }
+
+    #[test]
+    fn battery_status() {
+        let bus = device();
+        let alarm = SimulatedAlarm::new();
+        let hdc = HDC::new(&bus, &alarm);
+
+        assert_eq!(hdc.battery_low(), Ok(false));
+        bus.set_register(HDC_CONF_REG as u8, HDC_CONFIG_MODE | HDC_CONFIG_BTST);
+        assert_eq!(hdc.battery_low(), Ok(true));
+        bus.fail_next(I2cError::AddressNack);
+        assert_eq!(hdc.battery_low(), Err(I2cError::AddressNack));
+    }
+
+    #[test]
+    fn failed_trigger_is_reported() {
+        let bus = device();
+        let alarm = SimulatedAlarm::new();
+        let hdc = HDC::new(&bus, &alarm);
+        assert_eq!(hdc.init(), ReturnCode::SUCCESS);
+
+        bus.fail_next(I2cError::DataNack);
+        assert_eq!(hdc.read_temperature(), ReturnCode::ENOACK);
+        assert!(!alarm.is_armed());
+    }
+
+    #[test]
+    fn failed_readout_restarts_the_conversion() {
+        let bus = device();
+        let alarm = SimulatedAlarm::new();
+        let hdc = HDC::new(&bus, &alarm);
+        assert_eq!(hdc.init(), ReturnCode::SUCCESS);
+        let readings = subscribe(&hdc);
+        bus.set_register(HDC_TEMP_REG as u8, 0x8000);
+
+        assert_eq!(hdc.read_temperature(), ReturnCode::SUCCESS);
+        bus.fail_next(I2cError::DataNack);
+        convert(&hdc, &alarm);
+        assert_eq!(readings.temperature.get(), None);
+
+        convert(&hdc, &alarm);
+        assert_eq!(readings.temperature.get(), Some(4250));
+    }
+
+    #[test]
+    fn readout_gives_up_after_the_retries() {
+        let bus = device();
+        let alarm = SimulatedAlarm::new();
+        let hdc = HDC::new(&bus, &alarm);
+        assert_eq!(hdc.init(), ReturnCode::SUCCESS);
+        let readings = subscribe(&hdc);
+
+        assert_eq!(hdc.read_temperature(), ReturnCode::SUCCESS);
+        for _ in 0..(HDC_MAX_RETRIES + 1) {
+            bus.fail_next(I2cError::DataNack);
+            convert(&hdc, &alarm);
+        }
+        assert!(!alarm.is_armed());
+        assert_eq!(readings.temperature.get(), Some(sensor::READING_FAILED));
+        assert_eq!(readings.humidity.get(), None);
+
+        // The next request starts over
+        assert_eq!(hdc.read_temperature(), ReturnCode::SUCCESS);
+        assert!(alarm.is_armed());
     }
 }
@@ -1,14 +1,23 @@
 use prcm;
 use ioc;
+use osc;
+use rtc;
 use cc26xx::gpio;
+use kernel::hil;
 use kernel::hil::gpio::Pin;
+use kernel::hil::i2c;
+use kernel::ReturnCode;
 use core::cell::Cell;
+use core::cmp;
 use kernel::common::VolatileCell;
+use kernel::common::take_cell::TakeCell;
 
 pub const I2C_MCR_MFE: u32 = 0x10;
+pub const I2C_MCR_SFE: u32 = 0x20;
 pub const I2C_MCTRL_RUN: u32 = 0x1;
 
 pub const I2C_MASTER_CMD_SINGLE_SEND: u32 = 0x7;
+pub const I2C_MASTER_CMD_SINGLE_RECEIVE: u32 = 0x7;
 pub const I2C_MASTER_CMD_BURST_SEND_ERROR_STOP: u32 = 0x4;
 pub const I2C_MASTER_CMD_BURST_RECEIVE_START: u32 = 0xb;
 pub const I2C_MASTER_CMD_BURST_RECEIVE_CONT: u32 = 0x9;
@@ -26,12 +35,45 @@ pub const I2C_MSTAT_ADRACK_N: u32 = 0x4;
 pub const I2C_MSTAT_DATACK_N_M: u32 = 0x8;
 pub const I2C_MSTAT_ADRACK_N_M: u32 = 0x4;
 
+pub const I2C_MIMR_IM: u32 = 0x1;
+pub const I2C_MMIS_MIS: u32 = 0x1;
+pub const I2C_MICR_IC: u32 = 0x1;
+
+pub const I2C_SCTL_DA: u32 = 0x1;
+pub const I2C_SSTAT_RREQ: u32 = 0x1;
+pub const I2C_SSTAT_TREQ: u32 = 0x2;
+pub const I2C_SOAR_OAR: u32 = 0x7F;
+
+pub const I2C_SLAVE_INT_DATA: u32 = 0x1;
+pub const I2C_SLAVE_INT_START: u32 = 0x2;
+pub const I2C_SLAVE_INT_STOP: u32 = 0x4;
+pub const I2C_SLAVE_INT_ALL: u32 = 0x7;
+
+// Sent to a master reading from us when no data has been supplied
+pub const I2C_SLAVE_IDLE_BYTE: u8 = 0xFF;
+
 pub const BOARD_IO_SDA: usize = 0x5;
 pub const BOARD_IO_SCL: usize = 0x6;
 pub const BOARD_IO_SDA_HP: usize = 0x8;
 pub const BOARD_IO_SCL_HP: usize = 0x9;
 
-pub const MCU_CLOCK: u32 = 48_000_000;
+// Interrupt number of the I2C block, pended by hand to defer callbacks
+const I2C0_IRQ: u32 = 1;
+const NVIC_ISPR0: *const VolatileCell<u32> = 0xE000_E200 as *const VolatileCell<u32>;
+
+// Default upper bound for the master (or the bus) to become idle
+pub const I2C_DEFAULT_TIMEOUT_US: u32 = 25_000;
+
+// Valid range of the SCL period register (MTPR.TPR)
+pub const I2C_MTPR_TPR_MIN: u32 = 1;
+pub const I2C_MTPR_TPR_MAX: u32 = 0x7F;
+
+// Half an SCL period while bit-banging the bus. Delays last at least one
+// period of the 32kHz RTC clock (~30.5us), so the actual half period is
+// 30-61us, which is fine for recovery.
+pub const RECOVERY_HALF_PERIOD_US: u32 = 5;
+// Clock pulses required to free a slave stuck in the middle of a byte
+pub const RECOVERY_PULSES: usize = 9;
 
 #[derive(PartialEq, Debug, Copy, Clone)]
 pub enum I2cInterface {
@@ -40,6 +82,86 @@ pub enum I2cInterface {
     NoInterface = 2,
 }
 
+/// Reasons for a failed transfer, decoded from the master status register.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum I2cError {
+    /// The slave did not acknowledge its address
+    AddressNack,
+    /// The slave did not acknowledge a data byte
+    DataNack,
+    /// Another master won the bus during the transfer
+    ArbitrationLost,
+    /// The master or the bus did not become idle in time
+    Timeout,
+    /// An asynchronous transfer is using the controller, or another master
+    /// the bus
+    Busy,
+    /// The length of a transfer is zero or exceeds its buffer
+    InvalidLength,
+}
+
+impl I2cError {
+    /// Decodes the error bits returned by `master_err`, `None` if there is no error.
+    pub fn from_status(status: u32) -> Option<I2cError> {
+        if status & I2C_MSTAT_ARBLST != 0 {
+            Some(I2cError::ArbitrationLost)
+        } else if status & I2C_MSTAT_ADRACK_N != 0 {
+            Some(I2cError::AddressNack)
+        } else if status & I2C_MSTAT_DATACK_N != 0 {
+            Some(I2cError::DataNack)
+        } else {
+            None
+        }
+    }
+
+    // The HIL only has bus errors: a busy controller or bus is reported like
+    // a lost arbitration, and so is a timeout, an invalid length like a byte
+    // that was not acknowledged
+    fn hil_error(&self) -> i2c::Error {
+        match *self {
+            I2cError::AddressNack => i2c::Error::AddressNak,
+            I2cError::DataNack | I2cError::InvalidLength => i2c::Error::DataNak,
+            I2cError::ArbitrationLost | I2cError::Timeout | I2cError::Busy => {
+                i2c::Error::ArbitrationLost
+            }
+        }
+    }
+}
+
+impl From<I2cError> for ReturnCode {
+    fn from(err: I2cError) -> ReturnCode {
+        match err {
+            I2cError::AddressNack => ReturnCode::ENODEVICE,
+            I2cError::DataNack => ReturnCode::ENOACK,
+            I2cError::ArbitrationLost => ReturnCode::EBUSY,
+            I2cError::Timeout => ReturnCode::FAIL,
+            I2cError::Busy => ReturnCode::EBUSY,
+            I2cError::InvalidLength => ReturnCode::ESIZE,
+        }
+    }
+}
+
+/// SCL frequency used when talking to a device.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum I2cSpeed {
+    /// 100 kHz
+    Standard,
+    /// 400 kHz
+    Fast,
+    /// Any other frequency in Hz, limited by what the SCL divider can produce
+    Custom(u32),
+}
+
+impl I2cSpeed {
+    pub fn frequency(&self) -> u32 {
+        match *self {
+            I2cSpeed::Standard => 100_000,
+            I2cSpeed::Fast => 400_000,
+            I2cSpeed::Custom(freq) => freq,
+        }
+    }
+}
+
 #[repr(C)]
 pub struct Registers {
     pub soar: VolatileCell<u32>,
@@ -67,10 +189,59 @@ pub const I2C_BASE: *mut Registers = 0x4000_2000 as *mut Registers;
 
 pub static mut I2C0: I2C = I2C::new();
 
+/*
+    State of an interrupt driven (asynchronous) transfer. The blocking
+    transfers never leave the idle state, and fail with `I2cError::Busy`
+    while it is not idle.
+*/
+#[derive(PartialEq, Debug, Copy, Clone)]
+enum TransferState {
+    Idle,
+    Write,
+    Read,
+    WriteReadWrite,
+    WriteReadRead,
+}
+
+/*
+    Direction of the transfer the slave is currently taking part in, as seen
+    from the remote master.
+*/
+#[derive(PartialEq, Debug, Copy, Clone)]
+enum SlaveState {
+    Idle,
+    // The master writes to us
+    Receiving,
+    // The master reads from us
+    Sending,
+}
+
 pub struct I2C {
     regs: *mut Registers,
     slave_addr: Cell<u8>,
     interface: Cell<u8>,
+    speed: Cell<I2cSpeed>,
+    timeout_us: Cell<u32>,
+
+    master_client: Cell<Option<&'static i2c::I2CHwMasterClient>>,
+    master_interface: Cell<I2cInterface>,
+    timeout_alarm: Cell<Option<&'static hil::time::Alarm<Frequency = rtc::Freq65KHz>>>,
+    buffer: TakeCell<'static, [u8]>,
+    state: Cell<TransferState>,
+    write_len: Cell<u8>,
+    read_len: Cell<u8>,
+    index: Cell<u8>,
+    // A transfer handed back from the interrupt handler
+    rejected: TakeCell<'static, [u8]>,
+    rejected_error: Cell<I2cError>,
+
+    slave_client: Cell<Option<&'static i2c::I2CHwSlaveClient>>,
+    slave_write_buffer: TakeCell<'static, [u8]>,
+    slave_write_len: Cell<u8>,
+    slave_read_buffer: TakeCell<'static, [u8]>,
+    slave_read_len: Cell<u8>,
+    slave_state: Cell<SlaveState>,
+    slave_index: Cell<u8>,
 }
 
 impl I2C {
@@ -79,6 +250,67 @@ impl I2C {
             regs: I2C_BASE as *mut Registers,
             slave_addr: Cell::new(0),
             interface: Cell::new(I2cInterface::NoInterface as u8),
+            speed: Cell::new(I2cSpeed::Fast),
+            timeout_us: Cell::new(I2C_DEFAULT_TIMEOUT_US),
+
+            master_client: Cell::new(None),
+            master_interface: Cell::new(I2cInterface::Interface0),
+            timeout_alarm: Cell::new(None),
+            buffer: TakeCell::empty(),
+            state: Cell::new(TransferState::Idle),
+            write_len: Cell::new(0),
+            read_len: Cell::new(0),
+            index: Cell::new(0),
+            rejected: TakeCell::empty(),
+            rejected_error: Cell::new(I2cError::Busy),
+
+            slave_client: Cell::new(None),
+            slave_write_buffer: TakeCell::empty(),
+            slave_write_len: Cell::new(0),
+            slave_read_buffer: TakeCell::empty(),
+            slave_read_len: Cell::new(0),
+            slave_state: Cell::new(SlaveState::Idle),
+            slave_index: Cell::new(0),
+        }
+    }
+
+    pub fn set_slave_client(&self, client: &'static i2c::I2CHwSlaveClient) {
+        self.slave_client.set(Some(client));
+    }
+
+    pub fn set_master_client(&self, client: &'static i2c::I2CHwMasterClient) {
+        self.master_client.set(Some(client));
+    }
+
+    /// Sets the interface asynchronous transfers are performed on,
+    /// `Interface0` by default.
+    pub fn set_master_interface(&self, interface: I2cInterface) -> ReturnCode {
+        if interface == I2cInterface::NoInterface {
+            return ReturnCode::EINVAL;
+        }
+        self.master_interface.set(interface);
+        ReturnCode::SUCCESS
+    }
+
+    /// Sets the alarm bounding asynchronous transfers, which fail once the
+    /// timeout of the blocking transfers has passed for every byte. The I2C
+    /// controller has to be the client of the alarm. Without an alarm a
+    /// stuck transfer never completes.
+    pub fn set_timeout_alarm(&self, alarm: &'static hil::time::Alarm<Frequency = rtc::Freq65KHz>) {
+        self.timeout_alarm.set(Some(alarm));
+    }
+
+    /// Whether an asynchronous transfer is in progress
+    pub fn is_busy(&self) -> bool {
+        self.state.get() != TransferState::Idle
+    }
+
+    // Blocking transfers have to wait for the asynchronous one to be over
+    fn idle(&self) -> Result<(), I2cError> {
+        if self.is_busy() {
+            Err(I2cError::Busy)
+        } else {
+            Ok(())
         }
     }
 
@@ -87,7 +319,7 @@ impl I2C {
         while !prcm::Power::is_enabled(prcm::PowerDomain::Serial) { };
         prcm::Clock::enable_i2c();
 
-        self.configure(true);
+        self.configure(self.speed.get());
     }
 
     #[allow(unused)]
@@ -95,18 +327,62 @@ impl I2C {
         // Not implemented
     }
 
-    fn configure(&self, fast: bool) {
+    fn configure(&self, speed: I2cSpeed) {
         self.master_enable();
 
-        let freq;
-        if fast { freq = 400_000; } else { freq = 100_000; }
+        let freq = cmp::max(speed.frequency(), 1);
+        // The controller runs on the bus clock, SCLK_HF divided for the CPU
+        let clock = osc::OSCILLATOR_CONTROL.sclk_hf_freq() / prcm::Clock::cpu_clock_divider();
 
         // Compute SCL (serial clock) period
-        let tpr = ((MCU_CLOCK + (2 * 10 * freq) - 1) / (2 * 10 * freq)) - 1;
+        let tpr = ((clock + (2 * 10 * freq) - 1) / (2 * 10 * freq)).saturating_sub(1);
+        let tpr = cmp::min(cmp::max(tpr, I2C_MTPR_TPR_MIN), I2C_MTPR_TPR_MAX);
         let regs: &Registers = unsafe { &*self.regs };
         regs.mtpr.set(tpr);
     }
 
+    /// Sets how long a blocking transfer waits for the master or the bus
+    /// before failing with `I2cError::Timeout`.
+    pub fn set_timeout_us(&self, timeout_us: u32) {
+        self.timeout_us.set(timeout_us);
+    }
+
+    pub fn set_timeout_ms(&self, timeout_ms: u32) {
+        self.timeout_us.set(timeout_ms.saturating_mul(1000));
+    }
+
+    pub fn timeout_us(&self) -> u32 {
+        self.timeout_us.get()
+    }
+
+    pub fn speed(&self) -> I2cSpeed {
+        self.speed.get()
+    }
+
+    pub fn interface(&self) -> I2cInterface {
+        match self.interface.get() {
+            0 => I2cInterface::Interface0,
+            1 => I2cInterface::Interface1,
+            _ => I2cInterface::NoInterface,
+        }
+    }
+
+    pub fn address(&self) -> u8 {
+        self.slave_addr.get()
+    }
+
+    /// Changes the SCL frequency, the controller is reconfigured if it is running.
+    pub fn set_speed(&self, speed: I2cSpeed) {
+        if speed == self.speed.get() {
+            return;
+        }
+
+        self.speed.set(speed);
+        if self.accessible() {
+            self.configure(speed);
+        }
+    }
+
     fn master_enable(&self) {
         let regs: &Registers = unsafe { &*self.regs };
         // Set as master
@@ -121,130 +397,148 @@ impl I2C {
         regs.mcr.set(regs.mcr.get() & !I2C_MCR_MFE);
     }
 
-    pub fn write_single(&self, data: u8) -> bool {
+    pub fn write_single(&self, data: u8) -> Result<(), I2cError> {
+        self.idle()?;
         self.set_master_slave_address(self.slave_addr.get(), false);
         self.master_put_data(data);
 
-        if !self.busy_wait_master_bus() { return false; }
+        self.busy_wait_master_bus()?;
 
         self.master_control(I2C_MASTER_CMD_SINGLE_SEND);
-        if !self.busy_wait_master() { return false; }
+        self.busy_wait_master()?;
 
         self.status()
     }
 
-    pub fn read(&self, data: &mut [u8], len: u8) -> bool {
+    /// Reads a single byte. Nothing is written to the device beforehand, so
+    /// this also checks for a device without changing its state.
+    pub fn read_single(&self) -> Result<u8, I2cError> {
+        self.idle()?;
         self.set_master_slave_address(self.slave_addr.get(), true);
 
-        self.busy_wait_master_bus();
+        self.busy_wait_master_bus()?;
 
-        self.master_control(I2C_MASTER_CMD_BURST_RECEIVE_START);
+        self.master_control(I2C_MASTER_CMD_SINGLE_RECEIVE);
+        self.busy_wait_master()?;
+        self.status()?;
 
-        let mut i = 0;
-        let mut success = true;
-        while i < (len - 1) && success {
-            self.busy_wait_master();
-            success = self.status();
-            if success {
-                data[i as usize] = self.master_get_data() as u8;
-                self.master_control(I2C_MASTER_CMD_BURST_RECEIVE_CONT);
-                i += 1;
-            }
-        }
+        Ok(self.master_get_data() as u8)
+    }
 
-        if success {
-            self.master_control(I2C_MASTER_CMD_BURST_RECEIVE_FINISH);
-            self.busy_wait_master();
-            success = self.status();
-            if success {
-                data[(len - 1) as usize] = self.master_get_data() as u8;
-                self.busy_wait_master_bus();
-            }
-        }
+    pub fn read(&self, data: &mut [u8], len: u8) -> Result<(), I2cError> {
+        self.idle()?;
+        self.set_master_slave_address(self.slave_addr.get(), true);
+
+        self.busy_wait_master_bus()?;
 
-        success
+        self.master_control(I2C_MASTER_CMD_BURST_RECEIVE_START);
+
+        self.receive(data, len)
     }
 
-    pub fn write(&self, data: & [u8], len: u8) -> bool {
+    pub fn write(&self, data: & [u8], len: u8) -> Result<(), I2cError> {
+        self.idle()?;
         self.set_master_slave_address(self.slave_addr.get(), false);
 
         self.master_put_data(data[0]);
 
-        self.busy_wait_master_bus();
+        self.busy_wait_master_bus()?;
 
         self.master_control(I2C_MASTER_CMD_BURST_SEND_START);
-        self.busy_wait_master();
-        let mut success = self.status();
+        self.busy_wait_master()?;
+        self.status()?;
 
         for i in 1..len {
-            if !success { break; }
             self.master_put_data(data[i as usize]);
             if i < len - 1 {
                 self.master_control(I2C_MASTER_CMD_BURST_SEND_CONT);
-                self.busy_wait_master();
-                success = self.status();
+                self.busy_wait_master()?;
+                self.status()?;
             }
         }
 
-        if success {
-            self.master_control(I2C_MASTER_CMD_BURST_SEND_FINISH);
-            self.busy_wait_master();
-            success = self.status();
-            self.busy_wait_master_bus();
+        self.master_control(I2C_MASTER_CMD_BURST_SEND_FINISH);
+        self.busy_wait_master()?;
+        self.status()?;
+        self.busy_wait_master_bus()
+    }
+
+    /// Sends `prefix` (usually a register address) followed by `data` in a
+    /// single burst. The payload is streamed from the slice, so there is no
+    /// limit on its length.
+    pub fn write_prefixed(&self, prefix: u8, data: &[u8]) -> Result<(), I2cError> {
+        if data.is_empty() {
+            return self.write_single(prefix);
+        }
+
+        self.idle()?;
+        self.set_master_slave_address(self.slave_addr.get(), false);
+
+        self.master_put_data(prefix);
+
+        self.busy_wait_master_bus()?;
+
+        self.master_control(I2C_MASTER_CMD_BURST_SEND_START);
+        self.busy_wait_master()?;
+        self.status()?;
+
+        let last = data.len() - 1;
+        for (i, byte) in data.iter().enumerate() {
+            self.master_put_data(*byte);
+            if i == last {
+                self.master_control(I2C_MASTER_CMD_BURST_SEND_FINISH);
+            } else {
+                self.master_control(I2C_MASTER_CMD_BURST_SEND_CONT);
+            }
+            self.busy_wait_master()?;
+            self.status()?;
         }
 
-        success
+        self.busy_wait_master_bus()
     }
 
-    pub fn write_read(&self, data: &mut [u8], write_len: u8, read_len: u8) -> bool {
+    pub fn write_read(&self, data: &mut [u8], write_len: u8, read_len: u8) -> Result<(), I2cError> {
+        self.idle()?;
         self.set_master_slave_address(self.slave_addr.get(), false);
 
         self.master_put_data(data[0]);
 
-        self.busy_wait_master_bus();
+        self.busy_wait_master_bus()?;
 
         self.master_control(I2C_MASTER_CMD_BURST_SEND_START);
-        self.busy_wait_master();
-        let mut success = self.status();
+        self.busy_wait_master()?;
+        self.status()?;
 
         for i in 1..write_len {
-            if !success { break; }
-
             self.master_put_data(data[i as usize]);
 
             self.master_control(I2C_MASTER_CMD_BURST_SEND_CONT);
-            self.busy_wait_master();
-            success = self.status();
+            self.busy_wait_master()?;
+            self.status()?;
         }
 
-        if !success { return false; }
-
         self.set_master_slave_address(self.slave_addr.get(), true);
 
         self.master_control(I2C_MASTER_CMD_BURST_RECEIVE_START);
 
-        let mut i = 0;
-        while i < (read_len - 1) && success {
-            self.busy_wait_master();
-            success = self.status();
-            if success {
-                data[i as usize] = self.master_get_data() as u8;
-                self.master_control(I2C_MASTER_CMD_BURST_RECEIVE_CONT);
-                i += 1;
-            }
-        }
+        self.receive(data, read_len)
+    }
 
-        if success {
-            self.master_control(I2C_MASTER_CMD_BURST_RECEIVE_FINISH);
-            self.busy_wait_master();
-            success = self.status();
-            if success {
-                data[(read_len - 1) as usize] = self.master_get_data() as u8;
-                self.busy_wait_master_bus();
-            }
+    // Reads the bytes of a burst receive which has already been started
+    fn receive(&self, data: &mut [u8], len: u8) -> Result<(), I2cError> {
+        for i in 0..(len - 1) {
+            self.busy_wait_master()?;
+            self.status()?;
+            data[i as usize] = self.master_get_data() as u8;
+            self.master_control(I2C_MASTER_CMD_BURST_RECEIVE_CONT);
         }
 
-        success
+        self.master_control(I2C_MASTER_CMD_BURST_RECEIVE_FINISH);
+        self.busy_wait_master()?;
+        self.status()?;
+        data[(len - 1) as usize] = self.master_get_data() as u8;
+
+        self.busy_wait_master_bus()
     }
 
     fn set_master_slave_address(&self, addr: u8, receive: bool) {
@@ -272,26 +566,114 @@ impl I2C {
         (regs.mstat_mctrl.get() & I2C_MSTAT_BUSY) != 0
     }
 
-    // Limited busy wait for the master
-    fn busy_wait_master(&self) -> bool {
-        let delay = 0xFFFFFF;
-        for _ in 0..delay {
-            if !self.master_busy() {
-                return true;
+    // Limited busy wait for the master, the bus is recovered if it times out
+    fn busy_wait_master(&self) -> Result<(), I2cError> {
+        let deadline = rtc::Deadline::from_us(self.timeout_us.get());
+        while self.master_busy() {
+            if deadline.expired() {
+                self.recover_bus();
+                return Err(I2cError::Timeout);
+            }
+        }
+        Ok(())
+    }
+
+    // Limited busy wait for the master bus. If the bus stays busy we try
+    // to recover it once before giving up.
+    fn busy_wait_master_bus(&self) -> Result<(), I2cError> {
+        if self.wait_master_bus() {
+            return Ok(());
+        }
+
+        self.recover_bus();
+        if self.wait_master_bus() {
+            Ok(())
+        } else {
+            Err(I2cError::Timeout)
+        }
+    }
+
+    fn wait_master_bus(&self) -> bool {
+        let deadline = rtc::Deadline::from_us(self.timeout_us.get());
+        while self.master_bus_busy() {
+            if deadline.expired() {
+                return false;
             }
         }
-        false
+        true
+    }
+
+    /// Frees a bus where a slave holds SDA low, e.g. after it was reset in the
+    /// middle of a transfer. The pins of the current interface are temporarily
+    /// taken over as GPIOs, SCL is clocked nine times so the slave can finish
+    /// the byte it is sending, and a STOP condition is generated before the
+    /// pins are handed back to the I2C controller. Nothing is done while an
+    /// asynchronous transfer is in progress.
+    pub fn recover_bus(&self) {
+        if self.is_busy() {
+            return;
+        }
+        self.recover();
     }
 
-    // Limited busy wait for the master bus
-    fn busy_wait_master_bus(&self) -> bool {
-        let delay = 0xFFFFFF;
-        for _ in 0..delay {
-            if !self.master_bus_busy() {
-                return true;
+    fn recover(&self) {
+        let interface = self.interface.get();
+        let (sda, scl) = if interface == I2cInterface::Interface0 as u8 {
+            (BOARD_IO_SDA, BOARD_IO_SCL)
+        } else if interface == I2cInterface::Interface1 as u8 {
+            (BOARD_IO_SDA_HP, BOARD_IO_SCL_HP)
+        } else {
+            return;
+        };
+
+        self.master_disable();
+
+        unsafe {
+            let sda_pin = &gpio::PORT[sda];
+            let scl_pin = &gpio::PORT[scl];
+
+            // The bus is open drain, so lines are only ever released or driven low
+            Self::release_line(sda_pin, sda);
+            Self::release_line(scl_pin, scl);
+            Self::recovery_delay();
+
+            for _ in 0..RECOVERY_PULSES {
+                Self::drive_line_low(scl_pin);
+                Self::recovery_delay();
+                Self::release_line(scl_pin, scl);
+                Self::recovery_delay();
             }
+
+            // STOP condition: SDA goes high while SCL is high
+            Self::drive_line_low(scl_pin);
+            Self::recovery_delay();
+            Self::drive_line_low(sda_pin);
+            Self::recovery_delay();
+            Self::release_line(scl_pin, scl);
+            Self::recovery_delay();
+            Self::release_line(sda_pin, sda);
+            Self::recovery_delay();
+
+            // Hand the pins back to the I2C controller
+            ioc::IOCFG[sda].enable_i2c_sda();
+            ioc::IOCFG[scl].enable_i2c_scl();
         }
-        false
+
+        self.configure(self.speed.get());
+    }
+
+    unsafe fn release_line(pin: &gpio::GPIOPin, ioid: usize) {
+        pin.make_input();
+        ioc::IOCFG[ioid].set_input_mode(hil::gpio::InputMode::PullUp);
+    }
+
+    unsafe fn drive_line_low(pin: &gpio::GPIOPin) {
+        pin.clear();
+        pin.make_output();
+    }
+
+    fn recovery_delay() {
+        rtc::delay_us(RECOVERY_HALF_PERIOD_US);
     }
 
     fn master_control(&self, cmd: u32) {
@@ -299,14 +681,17 @@ impl I2C {
         regs.mstat_mctrl.set(cmd);
     }
 
-    fn status(&self) -> bool {
+    fn status(&self) -> Result<(), I2cError> {
         let status = self.master_err();
 
         if (status & (I2C_MSTAT_DATACK_N_M | I2C_MSTAT_ADRACK_N_M)) != 0 {
             self.master_control(I2C_MASTER_CMD_BURST_SEND_ERROR_STOP);
         }
 
-        status == 0
+        match I2cError::from_status(status) {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
     }
 
     fn master_err(&self) -> u32 {
@@ -338,38 +723,478 @@ impl I2C {
         true
     }
 
-    pub fn select(&self, new_interface: I2cInterface, addr: u8) {
+    /// Selects the device blocking transfers talk to. The controller is left
+    /// alone while an asynchronous transfer is in progress, the blocking
+    /// transfers fail with `I2cError::Busy` until it is over.
+    pub fn select(&self, new_interface: I2cInterface, addr: u8, speed: I2cSpeed) {
+        if self.is_busy() {
+            return;
+        }
+
         self.slave_addr.set(addr);
+        self.speed.set(speed);
 
         if !self.accessible() {
             self.wakeup();
         }
+        self.route(new_interface);
+        // The clock may have changed since the last transfer
+        self.configure(speed);
+    }
 
-        let interface= new_interface as u8;
-        if interface != self.interface.get() as u8 {
-            self.interface.set(interface);
+    /// Routes the I2C controller to one of the two sets of pins on the board.
+    /// Asynchronous transfers select the interface set with
+    /// `set_master_interface` themselves.
+    pub fn select_interface(&self, new_interface: I2cInterface) {
+        if self.is_busy() {
+            return;
+        }
+
+        if !self.accessible() {
+            self.wakeup();
+        }
 
-            self.master_disable();
+        if self.route(new_interface) {
+            self.configure(self.speed.get());
+        }
+    }
 
-            if interface == I2cInterface::Interface0 as u8 {
-                unsafe {
-                    ioc::IOCFG[BOARD_IO_SDA].enable_i2c_sda();
-                    ioc::IOCFG[BOARD_IO_SCL].enable_i2c_scl();
-                    gpio::PORT[BOARD_IO_SDA_HP].make_input();
-                    gpio::PORT[BOARD_IO_SCL_HP].make_input();
+    // Hands the pins of the interface to the controller, returns whether it
+    // has changed. The controller has to be configured again if it has.
+    fn route(&self, new_interface: I2cInterface) -> bool {
+        let interface = new_interface as u8;
+        if interface == self.interface.get() {
+            return false;
+        }
+
+        self.interface.set(interface);
+        self.master_disable();
+
+        if interface == I2cInterface::Interface0 as u8 {
+            unsafe {
+                ioc::IOCFG[BOARD_IO_SDA].enable_i2c_sda();
+                ioc::IOCFG[BOARD_IO_SCL].enable_i2c_scl();
+                gpio::PORT[BOARD_IO_SDA_HP].make_input();
+                gpio::PORT[BOARD_IO_SCL_HP].make_input();
+            }
+        } else if interface == I2cInterface::Interface1 as u8 {
+            unsafe {
+                ioc::IOCFG[BOARD_IO_SDA_HP].enable_i2c_sda();
+                ioc::IOCFG[BOARD_IO_SCL_HP].enable_i2c_scl();
+                gpio::PORT[BOARD_IO_SDA].make_input();
+                gpio::PORT[BOARD_IO_SCL].make_input();
+            }
+        }
+        true
+    }
+
+    fn master_enable_interrupt(&self) {
+        let regs: &Registers = unsafe { &*self.regs };
+        regs.micr.set(I2C_MICR_IC);
+        regs.mimr.set(I2C_MIMR_IM);
+    }
+
+    fn master_disable_interrupt(&self) {
+        let regs: &Registers = unsafe { &*self.regs };
+        regs.mimr.set(0);
+        regs.micr.set(I2C_MICR_IC);
+    }
+
+    /// Starts writing `len` bytes of `data` to `addr`. Once the transfer is
+    /// over the buffer is handed back through `command_complete` of the
+    /// master client, a transfer that cannot be started returns it right
+    /// away with the reason.
+    pub fn start_write(
+        &self,
+        addr: u8,
+        data: &'static mut [u8],
+        len: u8,
+    ) -> Result<(), (I2cError, &'static mut [u8])> {
+        if len == 0 || len as usize > data.len() {
+            return Err((I2cError::InvalidLength, data));
+        }
+        self.start_transfer(addr, data, TransferState::Write, len, 0)
+    }
+
+    /// Starts reading `len` bytes from `addr` into `data`, see `start_write`.
+    pub fn start_read(
+        &self,
+        addr: u8,
+        data: &'static mut [u8],
+        len: u8,
+    ) -> Result<(), (I2cError, &'static mut [u8])> {
+        if len == 0 || len as usize > data.len() {
+            return Err((I2cError::InvalidLength, data));
+        }
+        self.start_transfer(addr, data, TransferState::Read, 0, len)
+    }
+
+    /// Starts writing `write_len` bytes of `data` to `addr` and reading
+    /// `read_len` bytes back into `data` after a repeated start, see
+    /// `start_write`.
+    pub fn start_write_read(
+        &self,
+        addr: u8,
+        data: &'static mut [u8],
+        write_len: u8,
+        read_len: u8,
+    ) -> Result<(), (I2cError, &'static mut [u8])> {
+        let len = cmp::max(write_len, read_len) as usize;
+        if write_len == 0 || read_len == 0 || len > data.len() {
+            return Err((I2cError::InvalidLength, data));
+        }
+        self.start_transfer(addr, data, TransferState::WriteReadWrite, write_len, read_len)
+    }
+
+    /// Kicks off the first byte of an asynchronous transfer on the master
+    /// interface; the remaining bytes are driven from `handle_interrupt`.
+    /// The lengths have been checked against the buffer.
+    fn start_transfer(
+        &self,
+        addr: u8,
+        data: &'static mut [u8],
+        state: TransferState,
+        write_len: u8,
+        read_len: u8,
+    ) -> Result<(), (I2cError, &'static mut [u8])> {
+        if self.is_busy() {
+            return Err((I2cError::Busy, data));
+        }
+
+        self.select(self.master_interface.get(), addr, self.speed.get());
+        // Held by another master, or by a slave the blocking transfers will
+        // recover
+        if self.master_bus_busy() {
+            return Err((I2cError::Busy, data));
+        }
+
+        let first = data[0];
+        self.buffer.replace(data);
+
+        self.write_len.set(write_len);
+        self.read_len.set(read_len);
+        self.state.set(state);
+        self.master_enable_interrupt();
+        self.start_timeout(write_len as u32 + read_len as u32);
+
+        if state == TransferState::Read {
+            self.start_receive();
+            return Ok(());
+        }
+
+        self.set_master_slave_address(addr, false);
+        self.master_put_data(first);
+        self.index.set(1);
+
+        if state == TransferState::Write && write_len == 1 {
+            self.master_control(I2C_MASTER_CMD_SINGLE_SEND);
+        } else {
+            self.master_control(I2C_MASTER_CMD_BURST_SEND_START);
+        }
+        Ok(())
+    }
+
+    // Allows a transfer of `bytes` bytes the timeout of the blocking
+    // transfers for every byte, plus one for the address
+    fn start_timeout(&self, bytes: u32) {
+        let timeout_us = self.timeout_us.get().saturating_mul(bytes + 1);
+        self.timeout_alarm.get().map(|alarm| {
+            let ticks = rtc::ticks_from_us::<rtc::Freq65KHz>(timeout_us);
+            alarm.set_alarm(alarm.now().wrapping_add(ticks));
+        });
+    }
+
+    fn start_receive(&self) {
+        self.set_master_slave_address(self.slave_addr.get(), true);
+        self.index.set(0);
+
+        if self.read_len.get() == 1 {
+            self.master_control(I2C_MASTER_CMD_SINGLE_RECEIVE);
+        } else {
+            self.master_control(I2C_MASTER_CMD_BURST_RECEIVE_START);
+        }
+    }
+
+    /// Hands back the buffer of a transfer that could not be started. The
+    /// client is called from the interrupt handler rather than from within
+    /// its own request, by pending the interrupt of the controller. Only
+    /// one buffer is held, a client has to wait for it before retrying.
+    fn reject_transfer(&self, data: &'static mut [u8], err: I2cError) {
+        self.rejected.replace(data);
+        self.rejected_error.set(err);
+        unsafe { (*NVIC_ISPR0).set(1 << I2C0_IRQ) };
+    }
+
+    fn complete_rejected(&self) {
+        let err = self.rejected_error.get();
+        self.rejected.take().map(|buf| {
+            self.master_client.get().map(move |client| {
+                client.command_complete(buf, err.hil_error());
+            });
+        });
+    }
+
+    fn transfer_done(&self, err: i2c::Error) {
+        self.state.set(TransferState::Idle);
+        self.master_disable_interrupt();
+        self.timeout_alarm.get().map(|alarm| alarm.disable());
+
+        self.buffer.take().map(|buf| {
+            self.master_client.get().map(move |client| {
+                client.command_complete(buf, err);
+            });
+        });
+    }
+
+    pub fn handle_interrupt(&self) {
+        self.complete_rejected();
+        self.handle_master_interrupt();
+        self.handle_slave_interrupt();
+    }
+
+    fn handle_slave_interrupt(&self) {
+        let regs: &Registers = unsafe { &*self.regs };
+        let status = regs.smis.get();
+        regs.sicr.set(status);
+
+        if status & I2C_SLAVE_INT_START != 0 {
+            self.slave_index.set(0);
+            self.slave_state.set(SlaveState::Idle);
+        }
+
+        if status & I2C_SLAVE_INT_DATA != 0 {
+            let request = regs.sstat_sctl.get();
+            if request & I2C_SSTAT_RREQ != 0 {
+                self.slave_receive_byte();
+            } else if request & I2C_SSTAT_TREQ != 0 {
+                self.slave_send_byte();
+            }
+        }
+
+        if status & I2C_SLAVE_INT_STOP != 0 {
+            let length = self.slave_index.get();
+            match self.slave_state.get() {
+                SlaveState::Receiving => {
+                    self.slave_write_buffer.take().map(|buf| {
+                        self.slave_client.get().map(move |client| {
+                            client.command_complete(buf, length, i2c::SlaveTransmissionType::Write);
+                        });
+                    });
+                }
+                SlaveState::Sending => {
+                    self.slave_read_buffer.take().map(|buf| {
+                        self.slave_client.get().map(move |client| {
+                            client.command_complete(buf, length, i2c::SlaveTransmissionType::Read);
+                        });
+                    });
                 }
+                SlaveState::Idle => (),
             }
-                else if interface == I2cInterface::Interface1 as u8 {
-                    unsafe {
-                        ioc::IOCFG[BOARD_IO_SDA_HP].enable_i2c_sda();
-                        ioc::IOCFG[BOARD_IO_SCL_HP].enable_i2c_scl();
-                        gpio::PORT[BOARD_IO_SDA].make_input();
-                        gpio::PORT[BOARD_IO_SCL].make_input();
+            self.slave_state.set(SlaveState::Idle);
+        }
+    }
+
+    // The remote master has written a byte to us
+    fn slave_receive_byte(&self) {
+        let regs: &Registers = unsafe { &*self.regs };
+        self.slave_state.set(SlaveState::Receiving);
+
+        if self.slave_write_buffer.is_none() {
+            self.slave_client.get().map(|client| client.write_expected());
+        }
+
+        let data = regs.sdr.get() as u8;
+        let index = self.slave_index.get();
+        if index < self.slave_write_len.get() {
+            self.slave_write_buffer.map(|buf| {
+                buf[index as usize] = data;
+                self.slave_index.set(index + 1);
+            });
+        }
+    }
+
+    // The remote master wants to read a byte from us
+    fn slave_send_byte(&self) {
+        let regs: &Registers = unsafe { &*self.regs };
+        self.slave_state.set(SlaveState::Sending);
+
+        if self.slave_read_buffer.is_none() {
+            self.slave_client.get().map(|client| client.read_expected());
+        }
+
+        let index = self.slave_index.get();
+        let data = if index < self.slave_read_len.get() {
+            self.slave_read_buffer
+                .map(|buf| {
+                    self.slave_index.set(index + 1);
+                    buf[index as usize]
+                })
+                .unwrap_or(I2C_SLAVE_IDLE_BYTE)
+        } else {
+            I2C_SLAVE_IDLE_BYTE
+        };
+        regs.sdr.set(data as u32);
+    }
+
+    fn handle_master_interrupt(&self) {
+        let regs: &Registers = unsafe { &*self.regs };
+        let pending = regs.mmis.get() & I2C_MMIS_MIS != 0;
+        regs.micr.set(I2C_MICR_IC);
+
+        if !pending || self.state.get() == TransferState::Idle {
+            return;
+        }
+
+        let err = self.master_err();
+        if err != 0 {
+            if err & I2C_MSTAT_ARBLST == 0 {
+                self.master_control(I2C_MASTER_CMD_BURST_SEND_ERROR_STOP);
+            }
+
+            let error = I2cError::from_status(err).unwrap_or(I2cError::ArbitrationLost);
+            self.transfer_done(error.hil_error());
+            return;
+        }
+
+        let index = self.index.get();
+        match self.state.get() {
+            TransferState::Write => {
+                let len = self.write_len.get();
+                if index < len {
+                    self.buffer.map(|buf| self.master_put_data(buf[index as usize]));
+                    self.index.set(index + 1);
+                    if index == len - 1 {
+                        self.master_control(I2C_MASTER_CMD_BURST_SEND_FINISH);
+                    } else {
+                        self.master_control(I2C_MASTER_CMD_BURST_SEND_CONT);
                     }
+                } else {
+                    self.transfer_done(i2c::Error::CommandComplete);
                 }
+            }
+            TransferState::WriteReadWrite => {
+                if index < self.write_len.get() {
+                    self.buffer.map(|buf| self.master_put_data(buf[index as usize]));
+                    self.index.set(index + 1);
+                    self.master_control(I2C_MASTER_CMD_BURST_SEND_CONT);
+                } else {
+                    // Repeated start in order to read back from the slave
+                    self.state.set(TransferState::WriteReadRead);
+                    self.start_receive();
+                }
+            }
+            TransferState::Read | TransferState::WriteReadRead => {
+                let len = self.read_len.get();
+                let data = self.master_get_data() as u8;
+                self.buffer.map(|buf| buf[index as usize] = data);
+                self.index.set(index + 1);
+
+                if index + 1 >= len {
+                    self.transfer_done(i2c::Error::CommandComplete);
+                } else if index + 1 == len - 1 {
+                    self.master_control(I2C_MASTER_CMD_BURST_RECEIVE_FINISH);
+                } else {
+                    self.master_control(I2C_MASTER_CMD_BURST_RECEIVE_CONT);
+                }
+            }
+            TransferState::Idle => (),
+        }
+    }
+}
 
-            self.configure(true);
+// The transfer fails once the master has not made progress in time
+impl hil::time::Client for I2C {
+    fn fired(&self) {
+        if !self.is_busy() {
+            return;
         }
+
+        self.master_disable_interrupt();
+        self.recover();
+        self.transfer_done(I2cError::Timeout.hil_error());
     }
+}
 
+/// Transfers are performed one at a time on the master interface. A
+/// transfer requested while another is in progress, while the bus is held,
+/// or with an empty or oversized length is not started: its buffer comes
+/// back through a later `command_complete`, with `ArbitrationLost` for a
+/// busy controller or bus and `DataNak` for an invalid length. Use
+/// `start_write`, `start_read` and `start_write_read` to tell these apart
+/// from bus errors.
+impl i2c::I2CMaster for I2C {
+    fn enable(&self) {
+        if !self.accessible() {
+            self.wakeup();
+        }
+        self.master_enable();
+    }
+
+    fn disable(&self) {
+        self.master_disable_interrupt();
+        self.master_disable();
+    }
+
+    fn write_read(&self, addr: u8, data: &'static mut [u8], write_len: u8, read_len: u8) {
+        if let Err((err, data)) = self.start_write_read(addr, data, write_len, read_len) {
+            self.reject_transfer(data, err);
+        }
+    }
+
+    fn write(&self, addr: u8, data: &'static mut [u8], len: u8) {
+        if let Err((err, data)) = self.start_write(addr, data, len) {
+            self.reject_transfer(data, err);
+        }
+    }
+
+    fn read(&self, addr: u8, buffer: &'static mut [u8], len: u8) {
+        if let Err((err, buffer)) = self.start_read(addr, buffer, len) {
+            self.reject_transfer(buffer, err);
+        }
+    }
 }
+
+impl i2c::I2CSlave for I2C {
+    fn enable(&self) {
+        if !self.accessible() {
+            self.wakeup();
+        }
+
+        let regs: &Registers = unsafe { &*self.regs };
+        regs.mcr.set(regs.mcr.get() | I2C_MCR_SFE);
+        regs.sstat_sctl.set(I2C_SCTL_DA);
+    }
+
+    fn disable(&self) {
+        let regs: &Registers = unsafe { &*self.regs };
+        regs.simr.set(0);
+        regs.sicr.set(I2C_SLAVE_INT_ALL);
+        regs.sstat_sctl.set(0);
+        regs.mcr.set(regs.mcr.get() & !I2C_MCR_SFE);
+    }
+
+    fn set_address(&self, addr: u8) {
+        let regs: &Registers = unsafe { &*self.regs };
+        regs.soar.set((addr as u32) & I2C_SOAR_OAR);
+    }
+
+    fn write_receive(&self, data: &'static mut [u8], max_len: u8) {
+        let len = cmp::min(max_len as usize, data.len()) as u8;
+        self.slave_write_len.set(len);
+        self.slave_write_buffer.replace(data);
+    }
+
+    fn read_send(&self, data: &'static mut [u8], max_len: u8) {
+        let len = cmp::min(max_len as usize, data.len()) as u8;
+        self.slave_read_len.set(len);
+        self.slave_read_buffer.replace(data);
+    }
+
+    fn listen(&self) {
+        let regs: &Registers = unsafe { &*self.regs };
+        regs.sicr.set(I2C_SLAVE_INT_ALL);
+        regs.simr.set(I2C_SLAVE_INT_ALL);
+    }
+}
+
+impl i2c::I2CMasterSlave for I2C {}
@@ -1,7 +1,10 @@
 #![feature(asm, concat_idents, const_fn, const_cell_new, try_from)]
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![crate_name = "cc26x0"]
 #![crate_type = "rlib"]
+// The unit tests are built against std, which does not bring `core` in
+#[cfg(test)]
+extern crate core;
 extern crate cortexm3;
 #[allow(unused_imports)]
 #[macro_use]
@@ -12,11 +15,19 @@ extern crate cc26xx;
 extern crate bitfield;
 
 pub mod chip;
+#[cfg(not(test))]
 pub mod crt1;
 pub mod uart;
 pub mod i2c;
 pub mod sensor;
+#[cfg(test)]
+pub mod sim_bus;
 pub mod hdc;
+pub mod tmp007;
+pub mod bmp280;
+pub mod opt3001;
+pub mod mpu9250;
+pub mod batmon;
 pub mod aux;
 pub mod radio;
 pub mod timer;
@@ -31,4 +42,5 @@ pub mod gpio;
 #[allow(unused, unused_mut)]
 mod setup;
 
+#[cfg(not(test))]
 pub use crt1::init;
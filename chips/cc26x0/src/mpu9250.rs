@@ -0,0 +1,1366 @@
+//! MPU-9250 motion sensor
+//!
+//! The accelerometer and gyroscope are sampled continuously at the output
+//! data rate set by the sample rate divider, so a reading fetches the
+//! latest result. Results are delivered from an alarm rather than from
+//! within the request, as clients expect. A reading that fails on the bus
+//! is reported as `sensor::READING_FAILED` on all three axes.
+//!
+//! Acceleration is reported in milli-g and rotation in milli-degrees per
+//! second, both signed and per axis.
+//!
+//! The AK8963 magnetometer in the package is reached directly on the bus
+//! once the MPU's I2C bypass is enabled. Each magnetometer reading triggers
+//! a single 16 bit measurement, which is read back once an alarm signals it
+//! has completed. Readings are corrected with the factory sensitivity
+//! adjustment values and reported in nanotesla, in the axes of the
+//! accelerometer (the AK8963 has X and Y swapped and Z inverted).
+//!
+//! With wake-on-motion enabled the gyroscope is turned off and the
+//! accelerometer only wakes up at the low power output data rate, raising
+//! INT when the acceleration on any axis changes by more than the threshold.
+//! Gyroscope readings are refused in the meantime.
+//!
+//! The chip is powered from a rail switched by a GPIO pin, which is only
+//! turned on while the chip is in use: for a reading, or while
+//! wake-on-motion or burst sampling is enabled. After power-up the
+//! configuration is applied again. Readings as well as enabling
+//! wake-on-motion or burst sampling wait for the start-up time on the alarm,
+//! the motion client being told once the feature is enabled.
+//!
+//! `init` must be called before use: it powers the chip up, checks
+//! WHO_AM_I, applies the configuration and brings up the magnetometer, then
+//! powers it down again.
+//!
+//! For burst sampling the accelerometer and/or gyroscope samples are
+//! collected in the on-chip FIFO at the output data rate and handed to the
+//! motion client in batches. The MPU-9250 has no FIFO
+//! watermark interrupt, so the time it takes to collect the watermark
+//! number of samples is measured with an alarm. The FIFO overflow interrupt
+//! on INT triggers an early batch, flagged as having lost samples. Samples
+//! are stored as they come out of the FIFO: big endian 16 bit X, Y and Z
+//! acceleration followed by X, Y and Z rotation, in the configured ranges.
+//! Burst sampling and wake-on-motion exclude each other.
+
+use core::cell::Cell;
+use i2c::{I2cError, I2cInterface};
+use kernel::ReturnCode;
+use kernel::hil::gpio;
+use kernel::hil::sensors::{NineDof, NineDofClient};
+use kernel::hil::time::{self, Alarm};
+use rtc;
+use sensor::{self, RegisterBus, Sensor};
+
+pub const MPU_SMPLRT_DIV_REG: u8 = 0x19;
+pub const MPU_CONFIG_REG: u8 = 0x1A;
+pub const MPU_GYRO_CONFIG_REG: u8 = 0x1B;
+pub const MPU_ACCEL_CONFIG_REG: u8 = 0x1C;
+pub const MPU_ACCEL_CONFIG2_REG: u8 = 0x1D;
+pub const MPU_LP_ACCEL_ODR_REG: u8 = 0x1E;
+pub const MPU_WOM_THR_REG: u8 = 0x1F;
+pub const MPU_FIFO_EN_REG: u8 = 0x23;
+pub const MPU_INT_PIN_CFG_REG: u8 = 0x37;
+pub const MPU_INT_ENABLE_REG: u8 = 0x38;
+pub const MPU_INT_STATUS_REG: u8 = 0x3A;
+pub const MPU_ACCEL_XOUT_H_REG: u8 = 0x3B;
+pub const MPU_MOT_DETECT_CTRL_REG: u8 = 0x69;
+pub const MPU_USER_CTRL_REG: u8 = 0x6A;
+pub const MPU_GYRO_XOUT_H_REG: u8 = 0x43;
+pub const MPU_PWR_MGMT_1_REG: u8 = 0x6B;
+pub const MPU_PWR_MGMT_2_REG: u8 = 0x6C;
+pub const MPU_FIFO_COUNTH_REG: u8 = 0x72;
+pub const MPU_FIFO_R_W_REG: u8 = 0x74;
+pub const MPU_WHO_AM_I_REG: u8 = 0x75;
+
+pub const MPU_WHO_AM_I: u8 = 0x71;
+
+pub const MPU_PWR_MGMT_1_CLKSEL_AUTO: u8 = 0x01;
+pub const MPU_PWR_MGMT_1_CYCLE: u8 = 1 << 5;
+pub const MPU_PWR_MGMT_2_DISABLE_GYRO: u8 = 0x07;
+pub const MPU_FS_SEL_SHIFT: u8 = 3;
+pub const MPU_INT_PIN_CFG_LATCH_INT_EN: u8 = 1 << 5;
+pub const MPU_INT_PIN_CFG_INT_ANYRD_2CLEAR: u8 = 1 << 4;
+pub const MPU_INT_PIN_CFG_BYPASS_EN: u8 = 1 << 1;
+pub const MPU_INT_WOM: u8 = 1 << 6;
+pub const MPU_INT_FIFO_OFLOW: u8 = 1 << 4;
+pub const MPU_CONFIG_FIFO_MODE: u8 = 1 << 6; // Stop writing when full
+pub const MPU_FIFO_EN_GYRO: u8 = 0b111 << 4;
+pub const MPU_FIFO_EN_ACCEL: u8 = 1 << 3;
+pub const MPU_USER_CTRL_FIFO_EN: u8 = 1 << 6;
+pub const MPU_USER_CTRL_FIFO_RST: u8 = 1 << 2;
+pub const MPU_MOT_DETECT_CTRL_ACCEL_INTEL_EN: u8 = 1 << 7;
+pub const MPU_MOT_DETECT_CTRL_ACCEL_INTEL_MODE: u8 = 1 << 6; // Compare with the previous sample
+
+// Wake-on-motion threshold resolution
+pub const MPU_WOM_THR_MG_PER_LSB: u32 = 4;
+
+pub const MPU_FIFO_SIZE: usize = 512;
+pub const MPU_FIFO_COUNT_MASK: u16 = 0x1FFF;
+// Largest burst read, a whole number of samples of either size
+pub const MPU_FIFO_CHUNK: usize = 252;
+// Bytes per sensor in each FIFO sample
+pub const MPU_FIFO_AXES_LEN: usize = 6;
+// Internal sample rate the divider applies to
+pub const MPU_INTERNAL_RATE_HZ: u32 = 1000;
+
+// 41 Hz bandwidth, sets the internal sample rate to 1 kHz
+pub const MPU_CONFIG_DLPF_41HZ: u8 = 0x03;
+pub const MPU_ACCEL_CONFIG2_DLPF_41HZ: u8 = 0x03;
+pub const MPU_ACCEL_CONFIG2_DLPF_184HZ: u8 = 0x01;
+
+// Time from power-up until the registers are accessible
+pub const MPU_STARTUP_US: u32 = 100_000;
+
+pub const MPU_INTERFACE: I2cInterface = I2cInterface::Interface1;
+pub const MPU_ADDRESS: u8 = 0x68;
+
+pub const AK8963_WIA_REG: u8 = 0x00;
+pub const AK8963_ST1_REG: u8 = 0x02;
+pub const AK8963_CNTL1_REG: u8 = 0x0A;
+pub const AK8963_ASAX_REG: u8 = 0x10;
+
+pub const AK8963_WIA: u8 = 0x48;
+
+pub const AK8963_ST1_DRDY: u8 = 1 << 0;
+pub const AK8963_ST2_HOFL: u8 = 1 << 3; // Magnetic sensor overflow
+
+pub const AK8963_CNTL1_POWER_DOWN: u8 = 0x00;
+pub const AK8963_CNTL1_SINGLE: u8 = 0x01;
+pub const AK8963_CNTL1_FUSE_ROM: u8 = 0x0F;
+pub const AK8963_CNTL1_16BIT: u8 = 1 << 4;
+
+// ST1, six data bytes and ST2, reading ST2 ends the measurement
+pub const AK8963_DATA_LEN: usize = 8;
+
+// Magnetic flux density per LSB at 16 bit output
+pub const AK8963_NT_PER_LSB: i32 = 150;
+
+// Maximum single measurement time
+pub const AK8963_MEASUREMENT_US: u32 = 9_000;
+// Time to wait after entering power-down before switching modes
+pub const AK8963_MODE_CHANGE_US: u32 = 100;
+
+// Number of times the data is polled again if not ready yet
+pub const AK8963_MAX_RETRIES: u8 = 3;
+pub const AK8963_RETRY_US: u32 = 1_000;
+
+pub const AK8963_ADDRESS: u8 = 0x0C;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum AccelRange {
+    G2 = 0,
+    G4 = 1,
+    G8 = 2,
+    G16 = 3,
+}
+
+impl AccelRange {
+    pub fn from_usize(range: usize) -> Option<AccelRange> {
+        match range {
+            0 => Some(AccelRange::G2),
+            1 => Some(AccelRange::G4),
+            2 => Some(AccelRange::G8),
+            3 => Some(AccelRange::G16),
+            _ => None,
+        }
+    }
+
+    /// Full-scale in milli-g
+    fn full_scale(&self) -> i64 {
+        2000 << (*self as u8)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum GyroRange {
+    Dps250 = 0,
+    Dps500 = 1,
+    Dps1000 = 2,
+    Dps2000 = 3,
+}
+
+impl GyroRange {
+    pub fn from_usize(range: usize) -> Option<GyroRange> {
+        match range {
+            0 => Some(GyroRange::Dps250),
+            1 => Some(GyroRange::Dps500),
+            2 => Some(GyroRange::Dps1000),
+            3 => Some(GyroRange::Dps2000),
+            _ => None,
+        }
+    }
+
+    /// Full-scale in milli-degrees per second
+    fn full_scale(&self) -> i64 {
+        250_000 << (*self as u8)
+    }
+}
+
+/// Rate at which the accelerometer wakes up to sample in wake-on-motion
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum LowPowerRate {
+    Hz0_24 = 0,
+    Hz0_49 = 1,
+    Hz0_98 = 2,
+    Hz1_95 = 3,
+    Hz3_91 = 4,
+    Hz7_81 = 5,
+    Hz15_63 = 6,
+    Hz31_25 = 7,
+    Hz62_5 = 8,
+    Hz125 = 9,
+    Hz250 = 10,
+    Hz500 = 11,
+}
+
+impl LowPowerRate {
+    pub fn from_usize(rate: usize) -> Option<LowPowerRate> {
+        match rate {
+            0 => Some(LowPowerRate::Hz0_24),
+            1 => Some(LowPowerRate::Hz0_49),
+            2 => Some(LowPowerRate::Hz0_98),
+            3 => Some(LowPowerRate::Hz1_95),
+            4 => Some(LowPowerRate::Hz3_91),
+            5 => Some(LowPowerRate::Hz7_81),
+            6 => Some(LowPowerRate::Hz15_63),
+            7 => Some(LowPowerRate::Hz31_25),
+            8 => Some(LowPowerRate::Hz62_5),
+            9 => Some(LowPowerRate::Hz125),
+            10 => Some(LowPowerRate::Hz250),
+            11 => Some(LowPowerRate::Hz500),
+            _ => None,
+        }
+    }
+}
+
+/// Features enabled in the background
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Feature {
+    WakeOnMotion = 1,
+    Fifo = 2,
+}
+
+pub trait Mpu9250Client {
+    /// Called once enabling a feature has completed or failed
+    fn enabled(&self, feature: Feature, result: ReturnCode);
+
+    /// Called when motion has been detected while wake-on-motion is enabled
+    fn motion(&self);
+
+    /// Called with consecutive parts of a batch of FIFO samples, `offset`
+    /// being the position of `data` in the batch
+    fn fifo_data(&self, offset: usize, data: &[u8]);
+
+    /// Called once all the samples of a batch have been passed to
+    /// `fifo_data`, `overflow` being set if samples were lost before it
+    fn fifo_batch(&self, samples: usize, overflow: bool);
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Power {
+    Off,
+    Starting,
+    On,
+}
+
+// A feature waiting for the chip to be powered up
+#[derive(Copy, Clone, PartialEq)]
+enum Enable {
+    WakeOnMotion { threshold: u8, rate: LowPowerRate },
+    Fifo { fifo_enable: u8, watermark: usize },
+}
+
+impl Enable {
+    fn feature(&self) -> Feature {
+        match *self {
+            Enable::WakeOnMotion { .. } => Feature::WakeOnMotion,
+            Enable::Fifo { .. } => Feature::Fifo,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Reading {
+    Accelerometer,
+    Gyroscope,
+    Magnetometer,
+}
+
+pub struct MPU9250<'a, B: RegisterBus + 'a, A: Alarm + 'a, P: gpio::Pin + gpio::PinCtl + 'a> {
+    sensor: Cell<Sensor<'a, B>>,
+    magnetometer: Cell<Sensor<'a, B>>,
+    alarm: &'a A,
+    int_pin: &'a P,
+    power_pin: &'a P,
+    power: Cell<Power>,
+    power_due: Cell<u32>,
+    enabling: Cell<Option<Enable>>,
+    enable_due: Cell<u32>,
+    wake_on_motion: Cell<bool>,
+    present: Cell<bool>,
+    magnetometer_present: Cell<bool>,
+    sensitivity_adjustment: Cell<[u8; 3]>,
+    retries: Cell<u8>,
+    accel_range: Cell<AccelRange>,
+    gyro_range: Cell<GyroRange>,
+    sample_rate_divider: Cell<u8>,
+    pending: Cell<Option<Reading>>,
+    reading_due: Cell<u32>,
+    fifo_sensors: Cell<u8>,
+    fifo_sample_len: Cell<usize>,
+    fifo_period: Cell<u32>,
+    fifo_due: Cell<u32>,
+    fifo_overflow: Cell<bool>,
+    client: Cell<Option<&'static NineDofClient>>,
+    motion_client: Cell<Option<&'a Mpu9250Client>>,
+}
+
+impl<'a, B: RegisterBus + 'a, A: Alarm + 'a, P: gpio::Pin + gpio::PinCtl + 'a> MPU9250<'a, B, A, P> {
+    pub fn new(bus: &'a B, alarm: &'a A, int_pin: &'a P, power_pin: &'a P) -> MPU9250<'a, B, A, P> {
+        MPU9250 {
+            sensor: Cell::new(Sensor::new(bus, MPU_INTERFACE, MPU_ADDRESS)),
+            magnetometer: Cell::new(Sensor::new(bus, MPU_INTERFACE, AK8963_ADDRESS)),
+            alarm,
+            int_pin,
+            power_pin,
+            power: Cell::new(Power::Off),
+            power_due: Cell::new(0),
+            enabling: Cell::new(None),
+            enable_due: Cell::new(0),
+            wake_on_motion: Cell::new(false),
+            present: Cell::new(false),
+            magnetometer_present: Cell::new(false),
+            sensitivity_adjustment: Cell::new([128; 3]),
+            retries: Cell::new(0),
+            accel_range: Cell::new(AccelRange::G2),
+            gyro_range: Cell::new(GyroRange::Dps250),
+            sample_rate_divider: Cell::new(0),
+            pending: Cell::new(None),
+            reading_due: Cell::new(0),
+            fifo_sensors: Cell::new(0),
+            fifo_sample_len: Cell::new(0),
+            fifo_period: Cell::new(0),
+            fifo_due: Cell::new(0),
+            fifo_overflow: Cell::new(false),
+            client: Cell::new(None),
+            motion_client: Cell::new(None),
+        }
+    }
+
+    pub fn set_motion_client(&self, client: &'a Mpu9250Client) {
+        self.motion_client.set(Some(client));
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.present.get()
+    }
+
+    /// Checks WHO_AM_I, wakes the chip up and writes the configuration.
+    /// Readings are refused with `ENODEVICE` until this succeeds. The
+    /// magnetometer is optional: if it fails to come up only magnetometer
+    /// readings are refused.
+    pub fn init(&self) -> ReturnCode {
+        self.present.set(false);
+        self.magnetometer_present.set(false);
+
+        self.power_pin.make_output();
+        self.power_pin.set();
+        rtc::delay_us(MPU_STARTUP_US);
+
+        let result = self.identify();
+        self.power_pin.clear();
+        self.power.set(Power::Off);
+        result
+    }
+
+    fn identify(&self) -> ReturnCode {
+        match self.read_register(MPU_WHO_AM_I_REG) {
+            Ok(MPU_WHO_AM_I) => {}
+            Ok(_) => return ReturnCode::ENODEVICE,
+            Err(err) => return err.into(),
+        }
+
+        if let Err(err) = self.configure() {
+            return err.into();
+        }
+        self.present.set(true);
+
+        if let Ok(present) = self.init_magnetometer() {
+            self.magnetometer_present.set(present);
+        }
+        ReturnCode::SUCCESS
+    }
+
+    /// Applies the configuration after power-up
+    fn reinit(&self) -> Result<(), I2cError> {
+        self.configure()?;
+        if self.magnetometer_present.get() {
+            self.init_magnetometer()?;
+        }
+        Ok(())
+    }
+
+    /// Turns the rail on if needed, returns when the chip will be up
+    fn power_up(&self) -> u32 {
+        if self.power.get() == Power::Off {
+            // Plus one edge of the 32kHz clock since `now` may be read just
+            // before the counter advances
+            let ticks = rtc::ticks_from_us::<A::Frequency>(MPU_STARTUP_US) + rtc::RTC_TICKS_PER_EDGE;
+            self.power_pin.set();
+            self.power.set(Power::Starting);
+            self.power_due.set(self.alarm.now().wrapping_add(ticks));
+        }
+
+        if self.power.get() == Power::Starting {
+            self.power_due.get()
+        } else {
+            self.alarm.now()
+        }
+    }
+
+    // Called once the start-up time has passed
+    fn powered_up(&self) {
+        self.power.set(Power::On);
+        if let Err(err) = self.reinit() {
+            if self.pending.take().is_some() {
+                self.report_failure();
+            }
+            self.enabling.take().map(|enable| {
+                self.motion_client
+                    .get()
+                    .map(|client| client.enabled(enable.feature(), err.into()));
+            });
+            self.power_down_if_unused();
+        }
+    }
+
+    fn power_down_if_unused(&self) {
+        let unused = self.pending.get().is_none() && self.enabling.get().is_none();
+        if unused && !self.wake_on_motion.get() && !self.fifo_enabled() {
+            self.power_pin.clear();
+            self.power.set(Power::Off);
+        }
+    }
+
+    pub fn set_accel_range(&self, range: AccelRange) -> Result<(), I2cError> {
+        self.accel_range.set(range);
+        self.write_if_powered(MPU_ACCEL_CONFIG_REG, (range as u8) << MPU_FS_SEL_SHIFT)
+    }
+
+    pub fn set_gyro_range(&self, range: GyroRange) -> Result<(), I2cError> {
+        self.gyro_range.set(range);
+        self.write_if_powered(MPU_GYRO_CONFIG_REG, (range as u8) << MPU_FS_SEL_SHIFT)
+    }
+
+    /// Sets the output data rate to 1 kHz / (1 + divider)
+    pub fn set_sample_rate_divider(&self, divider: u8) -> Result<(), I2cError> {
+        self.sample_rate_divider.set(divider);
+        self.write_if_powered(MPU_SMPLRT_DIV_REG, divider)
+    }
+
+    /// Puts the chip in low power cycle mode, raising INT on motion above
+    /// `threshold` milli-g. Completes with `Mpu9250Client::enabled`.
+    pub fn enable_wake_on_motion(&self, threshold: u32, rate: LowPowerRate) -> ReturnCode {
+        if self.enabling.get().is_some() || self.fifo_enabled() {
+            return ReturnCode::EBUSY;
+        }
+
+        let threshold = threshold / MPU_WOM_THR_MG_PER_LSB;
+        let threshold = if threshold > 0xFF { 0xFF } else { threshold as u8 };
+        self.start_enable(Enable::WakeOnMotion { threshold, rate });
+        ReturnCode::SUCCESS
+    }
+
+    fn apply_wake_on_motion(&self, threshold: u8, rate: LowPowerRate) -> Result<(), I2cError> {
+        // Accelerometer only, running
+        self.write_register(MPU_PWR_MGMT_1_REG, MPU_PWR_MGMT_1_CLKSEL_AUTO)?;
+        self.write_register(MPU_PWR_MGMT_2_REG, MPU_PWR_MGMT_2_DISABLE_GYRO)?;
+        self.write_register(MPU_ACCEL_CONFIG2_REG, MPU_ACCEL_CONFIG2_DLPF_184HZ)?;
+
+        self.write_register(MPU_INT_ENABLE_REG, MPU_INT_WOM)?;
+        self.write_register(
+            MPU_MOT_DETECT_CTRL_REG,
+            MPU_MOT_DETECT_CTRL_ACCEL_INTEL_EN | MPU_MOT_DETECT_CTRL_ACCEL_INTEL_MODE,
+        )?;
+        self.write_register(MPU_WOM_THR_REG, threshold)?;
+        self.write_register(MPU_LP_ACCEL_ODR_REG, rate as u8)?;
+
+        // Clear a stale interrupt before listening for the pin
+        self.read_register(MPU_INT_STATUS_REG)?;
+        self.wake_on_motion.set(true);
+
+        // INT is push-pull and active high
+        self.int_pin.make_input();
+        self.int_pin.enable_interrupt(0, gpio::InterruptMode::RisingEdge);
+
+        self.write_register(
+            MPU_PWR_MGMT_1_REG,
+            MPU_PWR_MGMT_1_CLKSEL_AUTO | MPU_PWR_MGMT_1_CYCLE,
+        )
+    }
+
+    /// Starts collecting samples of the sensors selected by `fifo_enable`
+    /// (`MPU_FIFO_EN_ACCEL` and/or `MPU_FIFO_EN_GYRO`) in the FIFO,
+    /// delivering them in batches of `watermark` samples. Completes with
+    /// `Mpu9250Client::enabled`.
+    pub fn enable_fifo(&self, fifo_enable: u8, watermark: usize) -> ReturnCode {
+        // The batch has to fit in the FIFO
+        let sample_len = fifo_sample_len(fifo_enable);
+        let watermark_len = watermark * sample_len;
+        if sample_len == 0 || watermark == 0 || watermark_len > MPU_FIFO_SIZE - sample_len {
+            return ReturnCode::EINVAL;
+        }
+        if self.enabling.get().is_some() || self.wake_on_motion.get() {
+            return ReturnCode::EBUSY;
+        }
+
+        self.start_enable(Enable::Fifo { fifo_enable, watermark });
+        ReturnCode::SUCCESS
+    }
+
+    fn apply_fifo(&self, fifo_enable: u8, watermark: usize) -> Result<(), I2cError> {
+        let sample_len = fifo_sample_len(fifo_enable);
+        self.write_register(MPU_USER_CTRL_REG, 0)
+            .and_then(|_| self.write_register(MPU_FIFO_EN_REG, 0))
+            .and_then(|_| self.write_register(MPU_USER_CTRL_REG, MPU_USER_CTRL_FIFO_RST))
+            .and_then(|_| {
+                self.write_register(MPU_CONFIG_REG, MPU_CONFIG_DLPF_41HZ | MPU_CONFIG_FIFO_MODE)
+            })
+            .and_then(|_| self.write_register(MPU_FIFO_EN_REG, fifo_enable))
+            .and_then(|_| self.write_register(MPU_INT_ENABLE_REG, MPU_INT_FIFO_OFLOW))
+            // Clear a stale interrupt before listening for the pin
+            .and_then(|_| self.read_register(MPU_INT_STATUS_REG))
+            .and_then(|_| self.write_register(MPU_USER_CTRL_REG, MPU_USER_CTRL_FIFO_EN))?;
+
+        self.fifo_sensors.set(fifo_enable);
+        self.fifo_sample_len.set(sample_len);
+        self.fifo_overflow.set(false);
+
+        // INT is push-pull and active high
+        self.int_pin.make_input();
+        self.int_pin.enable_interrupt(0, gpio::InterruptMode::RisingEdge);
+
+        let rate = MPU_INTERNAL_RATE_HZ / (1 + self.sample_rate_divider.get() as u32);
+        let period_us = (watermark as u64 * 1_000_000 / rate as u64) as u32;
+        self.fifo_period.set(rtc::ticks_from_us::<A::Frequency>(period_us));
+        self.fifo_due.set(self.alarm.now().wrapping_add(self.fifo_period.get()));
+        Ok(())
+    }
+
+    fn start_enable(&self, enable: Enable) {
+        self.enabling.set(Some(enable));
+        // Applied from the alarm even if the chip is up already
+        self.enable_due.set(self.power_up());
+        self.rearm();
+    }
+
+    // Called once the chip is up for a feature to be enabled
+    fn complete_enable(&self, enable: Enable) {
+        let result = match enable {
+            Enable::WakeOnMotion { threshold, rate } => {
+                let result = self.apply_wake_on_motion(threshold, rate);
+                if result.is_err() {
+                    self.int_pin.disable_interrupt();
+                    self.wake_on_motion.set(false);
+                    self.power_down_if_unused();
+
+                    // Undo the partial configuration if still in use
+                    if self.power.get() == Power::On {
+                        let _ = self.reset_wake_on_motion();
+                    }
+                }
+                result
+            }
+            Enable::Fifo { fifo_enable, watermark } => {
+                let result = self.apply_fifo(fifo_enable, watermark);
+                if result.is_err() {
+                    self.power_down_if_unused();
+
+                    // Undo the partial configuration if still in use
+                    if self.power.get() == Power::On {
+                        let _ = self.reset_fifo();
+                    }
+                }
+                result
+            }
+        };
+
+        let result = match result {
+            Ok(()) => ReturnCode::SUCCESS,
+            Err(err) => err.into(),
+        };
+        self.motion_client
+            .get()
+            .map(|client| client.enabled(enable.feature(), result));
+    }
+
+    // Cancels a feature still waiting for the chip to be powered up
+    fn cancel_enable(&self, feature: Feature) -> bool {
+        match self.enabling.get() {
+            Some(enable) if enable.feature() == feature => {
+                self.enabling.set(None);
+                self.power_down_if_unused();
+                self.motion_client
+                    .get()
+                    .map(|client| client.enabled(feature, ReturnCode::ECANCEL));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn disable_fifo(&self) -> Result<(), I2cError> {
+        if self.cancel_enable(Feature::Fifo) || !self.fifo_enabled() {
+            return Ok(());
+        }
+
+        self.int_pin.disable_interrupt();
+        self.fifo_sensors.set(0);
+        self.rearm();
+
+        let result = self.reset_fifo();
+        self.power_down_if_unused();
+        result
+    }
+
+    fn reset_fifo(&self) -> Result<(), I2cError> {
+        self.write_register(MPU_USER_CTRL_REG, 0)?;
+        self.write_register(MPU_FIFO_EN_REG, 0)?;
+        self.write_register(MPU_INT_ENABLE_REG, 0)?;
+        self.write_register(MPU_CONFIG_REG, MPU_CONFIG_DLPF_41HZ)
+    }
+
+    pub fn fifo_enabled(&self) -> bool {
+        self.fifo_sensors.get() != 0
+    }
+
+    /// Bytes per FIFO sample while burst sampling
+    pub fn sample_len(&self) -> usize {
+        self.fifo_sample_len.get()
+    }
+
+    /// Passes the whole samples in the FIFO to the client
+    fn drain_fifo(&self) -> Result<(), I2cError> {
+        let sample_len = self.fifo_sample_len.get();
+
+        let mut buf = [0; 2];
+        self.read_registers(MPU_FIFO_COUNTH_REG, &mut buf)?;
+        let count = (((buf[0] as u16) << 8 | buf[1] as u16) & MPU_FIFO_COUNT_MASK) as usize;
+
+        // A full FIFO may end with a partial sample, so it has to be reset
+        // after reading to stay aligned
+        let full = count > MPU_FIFO_SIZE - sample_len;
+        if full {
+            self.fifo_overflow.set(true);
+        }
+
+        let len = count - count % sample_len;
+        let mut chunk = [0; MPU_FIFO_CHUNK];
+        let mut result = Ok(());
+        let mut offset = 0;
+        while offset < len && result.is_ok() {
+            let chunk_len = if len - offset < MPU_FIFO_CHUNK {
+                len - offset
+            } else {
+                MPU_FIFO_CHUNK
+            };
+            result = self.read_registers(MPU_FIFO_R_W_REG, &mut chunk[..chunk_len]);
+            if result.is_ok() {
+                self.motion_client
+                    .get()
+                    .map(|client| client.fifo_data(offset, &chunk[..chunk_len]));
+            }
+            offset += chunk_len;
+        }
+
+        if full {
+            self.write_register(
+                MPU_USER_CTRL_REG,
+                MPU_USER_CTRL_FIFO_EN | MPU_USER_CTRL_FIFO_RST,
+            )?;
+        }
+        result?;
+
+        let samples = len / sample_len;
+        if samples > 0 {
+            let overflow = self.fifo_overflow.get();
+            self.fifo_overflow.set(false);
+            self.motion_client
+                .get()
+                .map(|client| client.fifo_batch(samples, overflow));
+        }
+        Ok(())
+    }
+
+    /// Returns to continuous sampling of all sensors
+    pub fn disable_wake_on_motion(&self) -> Result<(), I2cError> {
+        if self.cancel_enable(Feature::WakeOnMotion) || !self.wake_on_motion.get() {
+            return Ok(());
+        }
+
+        self.int_pin.disable_interrupt();
+        self.wake_on_motion.set(false);
+
+        let result = self.reset_wake_on_motion();
+        self.power_down_if_unused();
+        result
+    }
+
+    fn reset_wake_on_motion(&self) -> Result<(), I2cError> {
+        self.write_register(MPU_INT_ENABLE_REG, 0)?;
+        self.write_register(MPU_MOT_DETECT_CTRL_REG, 0)?;
+        self.configure()
+    }
+
+    fn configure(&self) -> Result<(), I2cError> {
+        // Wake up, clocked from the gyroscope PLL when available
+        self.write_register(MPU_PWR_MGMT_1_REG, MPU_PWR_MGMT_1_CLKSEL_AUTO)?;
+        // All axes enabled
+        self.write_register(MPU_PWR_MGMT_2_REG, 0)?;
+
+        // Latched until any register is read. Bypass connects the
+        // auxiliary bus to the main one.
+        self.write_register(MPU_INT_PIN_CFG_REG, self.int_pin_cfg())?;
+
+        self.write_register(MPU_CONFIG_REG, MPU_CONFIG_DLPF_41HZ)?;
+        self.write_register(MPU_ACCEL_CONFIG2_REG, MPU_ACCEL_CONFIG2_DLPF_41HZ)?;
+        self.write_register(MPU_SMPLRT_DIV_REG, self.sample_rate_divider.get())?;
+        self.write_register(
+            MPU_GYRO_CONFIG_REG,
+            (self.gyro_range.get() as u8) << MPU_FS_SEL_SHIFT,
+        )?;
+        self.write_register(
+            MPU_ACCEL_CONFIG_REG,
+            (self.accel_range.get() as u8) << MPU_FS_SEL_SHIFT,
+        )
+    }
+
+    fn init_magnetometer(&self) -> Result<bool, I2cError> {
+        let magnetometer = self.magnetometer.get();
+        let mut buf = [0; 3];
+        read_registers(&magnetometer, AK8963_WIA_REG, &mut buf[..1])?;
+        if buf[0] != AK8963_WIA {
+            return Ok(false);
+        }
+
+        // The sensitivity adjustment values are only readable in fuse ROM mode
+        write_register(&magnetometer, AK8963_CNTL1_REG, AK8963_CNTL1_POWER_DOWN)?;
+        rtc::delay_us(AK8963_MODE_CHANGE_US);
+        write_register(&magnetometer, AK8963_CNTL1_REG, AK8963_CNTL1_FUSE_ROM)?;
+        read_registers(&magnetometer, AK8963_ASAX_REG, &mut buf)?;
+        self.sensitivity_adjustment.set(buf);
+        write_register(&magnetometer, AK8963_CNTL1_REG, AK8963_CNTL1_POWER_DOWN)?;
+        rtc::delay_us(AK8963_MODE_CHANGE_US);
+        Ok(true)
+    }
+
+    fn int_pin_cfg(&self) -> u8 {
+        MPU_INT_PIN_CFG_LATCH_INT_EN | MPU_INT_PIN_CFG_INT_ANYRD_2CLEAR | MPU_INT_PIN_CFG_BYPASS_EN
+    }
+
+    // Settings are applied with the configuration at power-up otherwise
+    fn write_if_powered(&self, reg: u8, value: u8) -> Result<(), I2cError> {
+        if self.power.get() == Power::On {
+            self.write_register(reg, value)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn read_register(&self, reg: u8) -> Result<u8, I2cError> {
+        let mut buf = [0; 1];
+        self.read_registers(reg, &mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn read_registers(&self, reg: u8, buf: &mut [u8]) -> Result<(), I2cError> {
+        read_registers(&self.sensor.get(), reg, buf)
+    }
+
+    fn write_register(&self, reg: u8, value: u8) -> Result<(), I2cError> {
+        write_register(&self.sensor.get(), reg, value)
+    }
+
+    // Reads three big endian axes starting at `reg`
+    fn read_axes(&self, reg: u8) -> Result<[i16; 3], I2cError> {
+        let mut buf = [0; 6];
+        self.read_registers(reg, &mut buf)?;
+        Ok([
+            ((buf[0] as u16) << 8 | buf[1] as u16) as i16,
+            ((buf[2] as u16) << 8 | buf[3] as u16) as i16,
+            ((buf[4] as u16) << 8 | buf[5] as u16) as i16,
+        ])
+    }
+
+    fn request(&self, reading: Reading) -> ReturnCode {
+        if !self.present.get() {
+            return ReturnCode::ENODEVICE;
+        }
+        if self.pending.get().is_some() {
+            return ReturnCode::EBUSY;
+        }
+        // The gyroscope is off in wake-on-motion
+        if reading == Reading::Gyroscope && self.wake_on_motion.get() {
+            return ReturnCode::EBUSY;
+        }
+        if reading == Reading::Magnetometer && !self.magnetometer_present.get() {
+            return ReturnCode::ENODEVICE;
+        }
+
+        // The reading starts once the chip is up
+        if self.power.get() != Power::On {
+            self.pending.set(Some(reading));
+            self.reading_due.set(self.power_up());
+            self.rearm();
+            return ReturnCode::SUCCESS;
+        }
+
+        match self.start_reading(reading) {
+            Ok(()) => ReturnCode::SUCCESS,
+            Err(err) => err.into(),
+        }
+    }
+
+    fn start_reading(&self, reading: Reading) -> Result<(), I2cError> {
+        let mut delay_us = 0;
+        if reading == Reading::Magnetometer {
+            write_register(
+                &self.magnetometer.get(),
+                AK8963_CNTL1_REG,
+                AK8963_CNTL1_SINGLE | AK8963_CNTL1_16BIT,
+            )?;
+            self.retries.set(0);
+            delay_us = AK8963_MEASUREMENT_US;
+        }
+
+        self.pending.set(Some(reading));
+        self.schedule_reading(delay_us);
+        Ok(())
+    }
+
+    fn schedule_reading(&self, us: u32) {
+        // Fire as soon as possible at the least
+        let ticks = rtc::ticks_from_us::<A::Frequency>(us) + 1;
+        self.reading_due.set(self.alarm.now().wrapping_add(ticks));
+        self.rearm();
+    }
+
+    /// The alarm serves readings, features being enabled and FIFO batches,
+    /// whichever is due first
+    fn rearm(&self) {
+        let now = self.alarm.now();
+        let earliest = |next: Option<u32>, due: u32| match next {
+            Some(next) if next.wrapping_sub(now) as i32 <= due.wrapping_sub(now) as i32 => Some(next),
+            _ => Some(due),
+        };
+
+        let mut next: Option<u32> = None;
+        if self.pending.get().is_some() {
+            next = earliest(next, self.reading_due.get());
+        }
+        if self.enabling.get().is_some() {
+            next = earliest(next, self.enable_due.get());
+        }
+        if self.fifo_enabled() {
+            next = earliest(next, self.fifo_due.get());
+        }
+
+        next.map(|due| {
+            // Already due, fire right away
+            if due.wrapping_sub(now) as i32 <= 0 {
+                self.alarm.set_alarm(now.wrapping_add(1));
+            } else {
+                self.alarm.set_alarm(due);
+            }
+        });
+    }
+
+    fn complete_reading(&self, reading: Reading) {
+        match reading {
+            Reading::Accelerometer => {
+                match self.read_axes(MPU_ACCEL_XOUT_H_REG) {
+                    Ok(raw) => {
+                        let range = self.accel_range.get();
+                        self.report([
+                            convert_accel(raw[0], range),
+                            convert_accel(raw[1], range),
+                            convert_accel(raw[2], range),
+                        ]);
+                    }
+                    Err(_) => self.report_failure(),
+                }
+            }
+            Reading::Gyroscope => {
+                match self.read_axes(MPU_GYRO_XOUT_H_REG) {
+                    Ok(raw) => {
+                        let range = self.gyro_range.get();
+                        self.report([
+                            convert_gyro(raw[0], range),
+                            convert_gyro(raw[1], range),
+                            convert_gyro(raw[2], range),
+                        ]);
+                    }
+                    Err(_) => self.report_failure(),
+                }
+            }
+            Reading::Magnetometer => match self.read_magnetometer_data() {
+                Ok(Some(field)) => self.report(field),
+                Ok(None) | Err(_) => {
+                    let retries = self.retries.get() + 1;
+                    self.retries.set(retries);
+                    if retries <= AK8963_MAX_RETRIES {
+                        self.pending.set(Some(reading));
+                        self.schedule_reading(AK8963_RETRY_US);
+                    } else {
+                        self.report_failure();
+                    }
+                }
+            },
+        }
+    }
+
+    /// Returns the adjusted field in the accelerometer axes, or `None` if
+    /// the measurement has not completed yet
+    fn read_magnetometer_data(&self) -> Result<Option<[i32; 3]>, I2cError> {
+        let mut buf = [0; AK8963_DATA_LEN];
+        read_registers(&self.magnetometer.get(), AK8963_ST1_REG, &mut buf)?;
+        if buf[0] & AK8963_ST1_DRDY == 0 {
+            return Ok(None);
+        }
+
+        // Little endian, X axis first
+        let raw = [
+            (buf[1] as u16 | (buf[2] as u16) << 8) as i16,
+            (buf[3] as u16 | (buf[4] as u16) << 8) as i16,
+            (buf[5] as u16 | (buf[6] as u16) << 8) as i16,
+        ];
+        let overflow = buf[7] & AK8963_ST2_HOFL != 0;
+        Ok(Some(magnetometer_field(raw, self.sensitivity_adjustment.get(), overflow)))
+    }
+
+    fn report(&self, values: [i32; 3]) {
+        self.client.get().map(|client| {
+            client.callback(
+                values[0] as isize as usize,
+                values[1] as isize as usize,
+                values[2] as isize as usize,
+            )
+        });
+    }
+
+    fn report_failure(&self) {
+        self.client.get().map(|client| {
+            client.callback(
+                sensor::READING_FAILED,
+                sensor::READING_FAILED,
+                sensor::READING_FAILED,
+            )
+        });
+    }
+}
+
+/// Acceleration in milli-g
+pub fn convert_accel(raw: i16, range: AccelRange) -> i32 {
+    (raw as i64 * range.full_scale() / 32768) as i32
+}
+
+/// Rotation in milli-degrees per second
+pub fn convert_gyro(raw: i16, range: GyroRange) -> i32 {
+    (raw as i64 * range.full_scale() / 32768) as i32
+}
+
+/// Bytes per FIFO sample of the sensors selected by `fifo_enable`
+pub fn fifo_sample_len(fifo_enable: u8) -> usize {
+    let mut sample_len = 0;
+    if fifo_enable & MPU_FIFO_EN_ACCEL != 0 {
+        sample_len += MPU_FIFO_AXES_LEN;
+    }
+    if fifo_enable & MPU_FIFO_EN_GYRO != 0 {
+        sample_len += MPU_FIFO_AXES_LEN;
+    }
+    sample_len
+}
+
+/// Magnetic field in nanotesla, adjusted with the axis' factory sensitivity
+/// adjustment value: raw * ((asa - 128) / 256 + 1)
+pub fn convert_magnetometer(raw: i16, asa: u8) -> i32 {
+    raw as i32 * (asa as i32 + 128) * AK8963_NT_PER_LSB / 256
+}
+
+/// Field in nanotesla in the accelerometer axes from the raw AK8963 axes,
+/// saturated in the direction of the field on magnetic sensor overflow
+pub fn magnetometer_field(raw: [i16; 3], asa: [u8; 3], overflow: bool) -> [i32; 3] {
+    let field = |axis: usize, sign: i32| {
+        // Z is inverted before saturating, as the negation of
+        // `i32::min_value()` overflows
+        let value = sign * convert_magnetometer(raw[axis], asa[axis]);
+        if !overflow {
+            value
+        } else if value < 0 {
+            i32::min_value()
+        } else {
+            i32::max_value()
+        }
+    };
+    [field(1, 1), field(0, 1), field(2, -1)]
+}
+
+fn read_registers<B: RegisterBus>(sensor: &Sensor<B>, reg: u8, buf: &mut [u8]) -> Result<(), I2cError> {
+    let len = buf.len() as u8;
+
+    sensor.select();
+    let result = sensor.read_from_reg(reg, buf, len);
+    sensor.deselect();
+    result
+}
+
+fn write_register<B: RegisterBus>(sensor: &Sensor<B>, reg: u8, value: u8) -> Result<(), I2cError> {
+    sensor.select();
+    let result = sensor.write_to_reg(reg, &[value]);
+    sensor.deselect();
+    result
+}
+
+impl<'a, B: RegisterBus + 'a, A: Alarm + 'a, P: gpio::Pin + gpio::PinCtl + 'a> time::Client
+    for MPU9250<'a, B, A, P>
+{
+    fn fired(&self) {
+        let now = self.alarm.now();
+        let expired = |due: u32| now.wrapping_sub(due) as i32 >= 0;
+
+        if self.power.get() == Power::Starting && expired(self.power_due.get()) {
+            self.powered_up();
+
+            // A reading waiting for the start-up time can start right away
+            self.pending.take().map(|reading| {
+                if self.start_reading(reading).is_err() {
+                    self.report_failure();
+                    self.power_down_if_unused();
+                }
+            });
+        } else if self.pending.get().is_some() && expired(self.reading_due.get()) {
+            self.pending.take().map(|reading| {
+                self.complete_reading(reading);
+                self.power_down_if_unused();
+            });
+        }
+
+        if self.power.get() == Power::On && expired(self.enable_due.get()) {
+            self.enabling.take().map(|enable| self.complete_enable(enable));
+        }
+
+        if self.fifo_enabled() && expired(self.fifo_due.get()) {
+            self.fifo_due.set(now.wrapping_add(self.fifo_period.get()));
+            let _ = self.drain_fifo();
+        }
+
+        self.rearm();
+    }
+}
+
+impl<'a, B: RegisterBus + 'a, A: Alarm + 'a, P: gpio::Pin + gpio::PinCtl + 'a> NineDof
+    for MPU9250<'a, B, A, P>
+{
+    fn set_client(&self, client: &'static NineDofClient) {
+        self.client.set(Some(client));
+    }
+
+    fn read_accelerometer(&self) -> ReturnCode {
+        self.request(Reading::Accelerometer)
+    }
+
+    fn read_gyroscope(&self) -> ReturnCode {
+        self.request(Reading::Gyroscope)
+    }
+
+    fn read_magnetometer(&self) -> ReturnCode {
+        self.request(Reading::Magnetometer)
+    }
+}
+
+impl<'a, B: RegisterBus + 'a, A: Alarm + 'a, P: gpio::Pin + gpio::PinCtl + 'a> gpio::Client
+    for MPU9250<'a, B, A, P>
+{
+    fn fired(&self, _: usize) {
+        // Reading the status clears the latched interrupt
+        let status = match self.read_register(MPU_INT_STATUS_REG) {
+            Ok(status) => status,
+            Err(_) => return,
+        };
+
+        if status & MPU_INT_WOM != 0 && self.wake_on_motion.get() {
+            self.motion_client.get().map(|client| client.motion());
+        }
+
+        // Deliver what was collected right away and restart the period
+        if status & MPU_INT_FIFO_OFLOW != 0 && self.fifo_enabled() {
+            self.fifo_overflow.set(true);
+            let _ = self.drain_fifo();
+            self.fifo_due.set(self.alarm.now().wrapping_add(self.fifo_period.get()));
+            self.rearm();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kernel::hil::gpio::Pin;
+    use kernel::hil::time::{Client, Time};
+    use sim_bus::{SimulatedAlarm, SimulatedBus, SimulatedPin};
+
+    const NO_ADJUSTMENT: [u8; 3] = [128, 128, 128];
+
+    const FAILED: (usize, usize, usize) = (
+        sensor::READING_FAILED,
+        sensor::READING_FAILED,
+        sensor::READING_FAILED,
+    );
+
+    struct Events {
+        enabled: Cell<Option<(Feature, ReturnCode)>>,
+    }
+
+    struct Readings {
+        values: Cell<Option<(usize, usize, usize)>>,
+    }
+
+    impl NineDofClient for Readings {
+        fn callback(&self, x: usize, y: usize, z: usize) {
+            self.values.set(Some((x, y, z)));
+        }
+    }
+
+    impl Mpu9250Client for Events {
+        fn enabled(&self, feature: Feature, result: ReturnCode) {
+            self.enabled.set(Some((feature, result)));
+        }
+
+        fn motion(&self) {}
+
+        fn fifo_data(&self, _offset: usize, _data: &[u8]) {}
+
+        fn fifo_batch(&self, _samples: usize, _overflow: bool) {}
+    }
+
+    fn events() -> Events {
+        Events {
+            enabled: Cell::new(None),
+        }
+    }
+
+    fn subscribe(mpu: &MPU9250<SimulatedBus, SimulatedAlarm, SimulatedPin>) -> &'static Readings {
+        let readings: &'static Readings = Box::leak(Box::new(Readings {
+            values: Cell::new(None),
+        }));
+        NineDof::set_client(mpu, readings);
+        readings
+    }
+
+    #[test]
+    fn failed_readout_is_reported() {
+        let bus = SimulatedBus::new(MPU_ADDRESS, 1);
+        let alarm = SimulatedAlarm::new();
+        let (int_pin, power_pin) = (SimulatedPin::new(), SimulatedPin::new());
+        let mpu = MPU9250::new(&bus, &alarm, &int_pin, &power_pin);
+        mpu.present.set(true);
+        let readings = subscribe(&mpu);
+
+        assert_eq!(mpu.read_accelerometer(), ReturnCode::SUCCESS);
+        // Powered up, the reading is served from the next alarm
+        assert!(alarm.expire());
+        mpu.fired();
+        assert_eq!(readings.values.get(), None);
+
+        bus.fail_next(I2cError::DataNack);
+        assert!(alarm.expire());
+        mpu.fired();
+        assert_eq!(readings.values.get(), Some(FAILED));
+        assert!(!power_pin.read());
+    }
+
+    #[test]
+    fn failed_power_up_fails_the_reading() {
+        let bus = SimulatedBus::new(MPU_ADDRESS, 1);
+        let alarm = SimulatedAlarm::new();
+        let (int_pin, power_pin) = (SimulatedPin::new(), SimulatedPin::new());
+        let mpu = MPU9250::new(&bus, &alarm, &int_pin, &power_pin);
+        mpu.present.set(true);
+        let readings = subscribe(&mpu);
+
+        assert_eq!(mpu.read_gyroscope(), ReturnCode::SUCCESS);
+        bus.fail_next(I2cError::AddressNack);
+        assert!(alarm.expire());
+        mpu.fired();
+        assert_eq!(readings.values.get(), Some(FAILED));
+        assert!(!power_pin.read());
+
+        // The next request starts over
+        assert_eq!(mpu.read_gyroscope(), ReturnCode::SUCCESS);
+    }
+
+    #[test]
+    fn wake_on_motion_waits_for_power_up_on_the_alarm() {
+        let bus = SimulatedBus::new(MPU_ADDRESS, 1);
+        let alarm = SimulatedAlarm::new();
+        let (int_pin, power_pin) = (SimulatedPin::new(), SimulatedPin::new());
+        let events = events();
+        let mpu = MPU9250::new(&bus, &alarm, &int_pin, &power_pin);
+        mpu.set_motion_client(&events);
+
+        assert_eq!(mpu.enable_wake_on_motion(400, LowPowerRate::Hz31_25), ReturnCode::SUCCESS);
+        assert!(power_pin.read());
+        assert_eq!(bus.register(MPU_INT_ENABLE_REG), 0);
+        assert_eq!(events.enabled.get(), None);
+        assert_eq!(
+            alarm.get_alarm(),
+            rtc::ticks_from_us::<rtc::Freq65KHz>(MPU_STARTUP_US) + rtc::RTC_TICKS_PER_EDGE
+        );
+
+        assert!(alarm.expire());
+        mpu.fired();
+        assert_eq!(events.enabled.get(), Some((Feature::WakeOnMotion, ReturnCode::SUCCESS)));
+        assert_eq!(bus.register(MPU_INT_ENABLE_REG), MPU_INT_WOM as u16);
+        assert_eq!(bus.register(MPU_WOM_THR_REG), 100);
+        assert_eq!(bus.register(MPU_LP_ACCEL_ODR_REG), LowPowerRate::Hz31_25 as u16);
+        assert!(bus.register(MPU_PWR_MGMT_1_REG) & MPU_PWR_MGMT_1_CYCLE as u16 != 0);
+        assert!(int_pin.interrupt_enabled());
+        assert!(power_pin.read());
+    }
+
+    #[test]
+    fn failed_wake_on_motion_powers_down() {
+        let bus = SimulatedBus::new(MPU_ADDRESS, 1);
+        let alarm = SimulatedAlarm::new();
+        let (int_pin, power_pin) = (SimulatedPin::new(), SimulatedPin::new());
+        let events = events();
+        let mpu = MPU9250::new(&bus, &alarm, &int_pin, &power_pin);
+        mpu.set_motion_client(&events);
+
+        assert_eq!(mpu.enable_wake_on_motion(400, LowPowerRate::Hz31_25), ReturnCode::SUCCESS);
+
+        // The configuration after power-up goes through, enabling
+        // wake-on-motion fails halfway
+        bus.fail_after(12, I2cError::DataNack);
+        assert!(alarm.expire());
+        mpu.fired();
+        assert_eq!(events.enabled.get(), Some((Feature::WakeOnMotion, ReturnCode::ENOACK)));
+        assert!(!int_pin.interrupt_enabled());
+        assert!(!power_pin.read());
+
+        // Nothing is left to enable
+        assert_eq!(mpu.enable_fifo(MPU_FIFO_EN_ACCEL, 10), ReturnCode::SUCCESS);
+    }
+
+    #[test]
+    fn disabling_before_power_up_cancels() {
+        let bus = SimulatedBus::new(MPU_ADDRESS, 1);
+        let alarm = SimulatedAlarm::new();
+        let (int_pin, power_pin) = (SimulatedPin::new(), SimulatedPin::new());
+        let events = events();
+        let mpu = MPU9250::new(&bus, &alarm, &int_pin, &power_pin);
+        mpu.set_motion_client(&events);
+
+        assert_eq!(mpu.enable_fifo(MPU_FIFO_EN_ACCEL, 10), ReturnCode::SUCCESS);
+        assert_eq!(mpu.enable_wake_on_motion(400, LowPowerRate::Hz31_25), ReturnCode::EBUSY);
+
+        assert_eq!(mpu.disable_fifo(), Ok(()));
+        assert_eq!(events.enabled.get(), Some((Feature::Fifo, ReturnCode::ECANCEL)));
+        assert!(!power_pin.read());
+
+        events.enabled.set(None);
+        alarm.expire();
+        mpu.fired();
+        assert_eq!(events.enabled.get(), None);
+        assert!(!mpu.fifo_enabled());
+    }
+
+    #[test]
+    fn fifo_is_enabled_from_the_alarm_when_powered() {
+        let bus = SimulatedBus::new(MPU_ADDRESS, 1);
+        let alarm = SimulatedAlarm::new();
+        let (int_pin, power_pin) = (SimulatedPin::new(), SimulatedPin::new());
+        let events = events();
+        let mpu = MPU9250::new(&bus, &alarm, &int_pin, &power_pin);
+        mpu.set_motion_client(&events);
+
+        assert_eq!(mpu.enable_wake_on_motion(400, LowPowerRate::Hz31_25), ReturnCode::SUCCESS);
+        alarm.expire();
+        mpu.fired();
+        assert_eq!(mpu.disable_wake_on_motion(), Ok(()));
+        assert!(!power_pin.read());
+
+        // Keep the chip up with a reading in progress, `init` would need the
+        // RTC to wait for the start-up time
+        mpu.present.set(true);
+        assert_eq!(mpu.read_accelerometer(), ReturnCode::SUCCESS);
+        alarm.expire();
+        mpu.fired();
+        assert!(power_pin.read());
+
+        events.enabled.set(None);
+        assert_eq!(mpu.enable_fifo(MPU_FIFO_EN_ACCEL | MPU_FIFO_EN_GYRO, 10), ReturnCode::SUCCESS);
+        assert_eq!(events.enabled.get(), None);
+        assert!(!mpu.fifo_enabled());
+
+        alarm.expire();
+        mpu.fired();
+        assert_eq!(events.enabled.get(), Some((Feature::Fifo, ReturnCode::SUCCESS)));
+        assert!(mpu.fifo_enabled());
+        assert_eq!(mpu.sample_len(), 2 * MPU_FIFO_AXES_LEN);
+    }
+
+    #[test]
+    fn magnetometer_axes_follow_the_accelerometer() {
+        let field = magnetometer_field([1, 2, 3], NO_ADJUSTMENT, false);
+        assert_eq!(field, [2 * AK8963_NT_PER_LSB, AK8963_NT_PER_LSB, -3 * AK8963_NT_PER_LSB]);
+    }
+
+    #[test]
+    fn magnetometer_sensitivity_adjustment() {
+        // ASA of 0 halves the raw value, 255 scales it by almost 1.5
+        assert_eq!(convert_magnetometer(100, 0), 50 * AK8963_NT_PER_LSB);
+        assert_eq!(convert_magnetometer(100, 255), 22441);
+        assert_eq!(convert_magnetometer(-100, 128), -100 * AK8963_NT_PER_LSB);
+        // Scaled before dividing, keeping the precision of the adjustment
+        assert_eq!(convert_magnetometer(3, 0), 225);
+        assert_eq!(convert_magnetometer(i16::max_value(), 255), 7_353_375);
+    }
+
+    #[test]
+    fn magnetometer_gives_up_after_the_retries() {
+        let bus = SimulatedBus::new(AK8963_ADDRESS, 1);
+        let alarm = SimulatedAlarm::new();
+        let (int_pin, power_pin) = (SimulatedPin::new(), SimulatedPin::new());
+        let mpu = MPU9250::new(&bus, &alarm, &int_pin, &power_pin);
+        mpu.present.set(true);
+        mpu.magnetometer_present.set(true);
+        mpu.power.set(Power::On);
+        let readings = subscribe(&mpu);
+
+        assert_eq!(mpu.read_magnetometer(), ReturnCode::SUCCESS);
+        let single = AK8963_CNTL1_SINGLE | AK8963_CNTL1_16BIT;
+        assert_eq!(bus.register(AK8963_CNTL1_REG), single as u16);
+
+        // The measurement never completes
+        for _ in 0..(AK8963_MAX_RETRIES + 1) {
+            assert_eq!(readings.values.get(), None);
+            assert!(alarm.expire());
+            mpu.fired();
+        }
+        assert_eq!(readings.values.get(), Some(FAILED));
+        assert!(!alarm.is_armed());
+    }
+
+    #[test]
+    fn magnetometer_overflow_saturates_in_the_field_direction() {
+        let max = i32::max_value();
+        let min = i32::min_value();
+
+        let field = magnetometer_field([-4000, 4000, -4000], NO_ADJUSTMENT, true);
+        assert_eq!(field, [max, min, max]);
+
+        let field = magnetometer_field([4000, -4000, 4000], NO_ADJUSTMENT, true);
+        assert_eq!(field, [min, max, min]);
+    }
+}
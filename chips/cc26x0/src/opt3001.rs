@@ -0,0 +1,770 @@
+//! OPT3001 ambient light sensor
+//!
+//! Each request starts a single-shot conversion, after which we wait on an
+//! alarm for the configured conversion time and read the result. The sensor
+//! shuts down again by itself once the conversion completes. A result that
+//! can't be read is polled again a few times, after which the client
+//! receives `sensor::READING_FAILED`.
+//!
+//! The result register holds a 4 bit exponent and a 12 bit mantissa, the
+//! light level being `0.01 lux * 2^exponent * mantissa`. With automatic
+//! full-scale ranging the sensor picks the exponent that gives the best
+//! resolution for the current light level.
+//!
+//! `init` must be called before use: it verifies the identification
+//! registers so that a missing or unexpected device is reported.
+//!
+//! When the limit window is enabled the sensor converts continuously in
+//! latched window mode, flagging every conversion outside of the low and
+//! high limits and pulling its INT pin low. The window client is told when
+//! the light level leaves the window, after which the sensor is polled at
+//! the conversion rate until the level is back inside. Without an INT pin
+//! the flags are polled all along. Light readings made in the meantime
+//! return the latest continuous result.
+
+use core::cell::Cell;
+use i2c::{I2cError, I2cInterface};
+use kernel::ReturnCode;
+use kernel::hil::gpio;
+use kernel::hil::sensors::{AmbientLight, AmbientLightClient};
+use kernel::hil::time::{self, Alarm, Time};
+use rtc;
+use sensor::{self, RegisterBus, Sensor};
+
+pub const OPT3001_RESULT_REG: u8 = 0x00;
+pub const OPT3001_CONFIG_REG: u8 = 0x01;
+pub const OPT3001_LOW_LIMIT_REG: u8 = 0x02;
+pub const OPT3001_HIGH_LIMIT_REG: u8 = 0x03;
+pub const OPT3001_MANUFACTURER_ID_REG: u8 = 0x7E;
+pub const OPT3001_DEVICE_ID_REG: u8 = 0x7F;
+
+pub const OPT3001_MANUFACTURER_ID: u16 = 0x5449; // Texas Instruments
+pub const OPT3001_DEVICE_ID: u16 = 0x3001;
+
+pub const OPT3001_CONFIG_RN_SHIFT: u16 = 12;
+pub const OPT3001_CONFIG_RN_AUTO: u16 = 0b1100 << OPT3001_CONFIG_RN_SHIFT;
+pub const OPT3001_CONFIG_CT: u16 = 1 << 11; // 800 ms conversion time
+pub const OPT3001_CONFIG_MODE_SHUTDOWN: u16 = 0b00 << 9;
+pub const OPT3001_CONFIG_MODE_SINGLE: u16 = 0b01 << 9;
+pub const OPT3001_CONFIG_MODE_CONTINUOUS: u16 = 0b10 << 9;
+pub const OPT3001_CONFIG_CRF: u16 = 1 << 7; // Conversion ready
+pub const OPT3001_CONFIG_FH: u16 = 1 << 6; // Above the high limit
+pub const OPT3001_CONFIG_FL: u16 = 1 << 5; // Below the low limit
+pub const OPT3001_CONFIG_L: u16 = 1 << 4; // Latched window mode
+
+pub const OPT3001_EXPONENT_SHIFT: u16 = 12;
+pub const OPT3001_MANTISSA_MASK: u16 = 0x0FFF;
+pub const OPT3001_MAX_EXPONENT: u16 = 11;
+
+// Margin added to the conversion times, which can be up to 10% longer
+// than nominal. Ranging adds a short extra conversion in automatic mode.
+pub const OPT3001_CONVERSION_MARGIN_MS: u32 = 20;
+
+// Number of times the result is polled again if not ready yet
+pub const OPT3001_MAX_RETRIES: u8 = 3;
+pub const OPT3001_RETRY_MS: u32 = 10;
+
+// Delay before serving a light reading while the window is enabled
+pub const OPT3001_READ_DELAY_MS: u32 = 1;
+
+pub const OPT3001_INTERFACE: I2cInterface = I2cInterface::Interface0;
+pub const OPT3001_ADDRESS: u8 = 0x45;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ConversionTime {
+    Ms100,
+    Ms800,
+}
+
+impl ConversionTime {
+    fn config_bits(&self) -> u16 {
+        match *self {
+            ConversionTime::Ms100 => 0,
+            ConversionTime::Ms800 => OPT3001_CONFIG_CT,
+        }
+    }
+
+    fn duration_ms(&self) -> u32 {
+        match *self {
+            ConversionTime::Ms100 => 110,
+            ConversionTime::Ms800 => 880,
+        }
+    }
+}
+
+/// Full-scale range, either chosen by the sensor or fixed to a given
+/// exponent (0 to 11, 40.95 lux full-scale at 0 doubling with each step)
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Range {
+    Auto,
+    Manual(u8),
+}
+
+impl Range {
+    fn config_bits(&self) -> u16 {
+        match *self {
+            Range::Auto => OPT3001_CONFIG_RN_AUTO,
+            Range::Manual(exponent) => (exponent as u16 & 0xF) << OPT3001_CONFIG_RN_SHIFT,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Limit {
+    High = 1,
+    Low = 2,
+}
+
+pub trait Opt3001Client {
+    /// Called when the light level has left the limit window, with the
+    /// light level in lux
+    fn left_window(&self, limit: Limit, lux: u32);
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum State {
+    Idle,
+    Converting,
+    Monitoring,
+}
+
+pub struct OPT3001<'a, B: RegisterBus + 'a, A: Alarm + 'a, P: gpio::Pin + gpio::PinCtl + 'a> {
+    sensor: Cell<Sensor<'a, B>>,
+    alarm: &'a A,
+    int_pin: Option<&'a P>,
+    state: Cell<State>,
+    present: Cell<bool>,
+    conversion_time: Cell<ConversionTime>,
+    range: Cell<Range>,
+    retries: Cell<u8>,
+    light_pending: Cell<bool>,
+    polling: Cell<bool>,
+    low_limit: Cell<u16>,
+    high_limit: Cell<u16>,
+    outside: Cell<Option<Limit>>,
+    client: Cell<Option<&'static AmbientLightClient>>,
+    window_client: Cell<Option<&'a Opt3001Client>>,
+}
+
+impl<'a, B: RegisterBus + 'a, A: Alarm + 'a, P: gpio::Pin + gpio::PinCtl + 'a> OPT3001<'a, B, A, P> {
+    /// `int_pin` is the pin INT is connected to, if any
+    pub fn new(bus: &'a B, alarm: &'a A, int_pin: Option<&'a P>) -> OPT3001<'a, B, A, P> {
+        OPT3001 {
+            sensor: Cell::new(Sensor::new(bus, OPT3001_INTERFACE, OPT3001_ADDRESS)),
+            alarm,
+            int_pin,
+            state: Cell::new(State::Idle),
+            present: Cell::new(false),
+            conversion_time: Cell::new(ConversionTime::Ms100),
+            range: Cell::new(Range::Auto),
+            retries: Cell::new(0),
+            light_pending: Cell::new(false),
+            polling: Cell::new(false),
+            low_limit: Cell::new(0),
+            high_limit: Cell::new(OPT3001_MANTISSA_MASK | OPT3001_MAX_EXPONENT << OPT3001_EXPONENT_SHIFT),
+            outside: Cell::new(None),
+            client: Cell::new(None),
+            window_client: Cell::new(None),
+        }
+    }
+
+    pub fn set_window_client(&self, client: &'a Opt3001Client) {
+        self.window_client.set(Some(client));
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.present.get()
+    }
+
+    /// Checks the identification registers and puts the sensor in shutdown.
+    /// Readings are refused with `ENODEVICE` until this succeeds.
+    pub fn init(&self) -> ReturnCode {
+        let result = self.identify().and_then(|present| {
+            if present {
+                self.write_register(OPT3001_CONFIG_REG, self.config(OPT3001_CONFIG_MODE_SHUTDOWN))
+                    .map(|_| true)
+            } else {
+                Ok(false)
+            }
+        });
+
+        match result {
+            Ok(present) => {
+                self.present.set(present);
+                if present {
+                    ReturnCode::SUCCESS
+                } else {
+                    ReturnCode::ENODEVICE
+                }
+            }
+            Err(err) => {
+                self.present.set(false);
+                err.into()
+            }
+        }
+    }
+
+    /// Applied with the next conversion
+    pub fn set_conversion_time(&self, conversion_time: ConversionTime) {
+        self.conversion_time.set(conversion_time);
+    }
+
+    /// Applied with the next conversion
+    pub fn set_range(&self, range: Range) {
+        self.range.set(range);
+    }
+
+    pub fn set_low_limit(&self, centilux: u32) -> Result<(), I2cError> {
+        let limit = convert_to_limit(centilux);
+        self.low_limit.set(limit);
+        self.write_register(OPT3001_LOW_LIMIT_REG, limit)
+    }
+
+    pub fn set_high_limit(&self, centilux: u32) -> Result<(), I2cError> {
+        let limit = convert_to_limit(centilux);
+        self.high_limit.set(limit);
+        self.write_register(OPT3001_HIGH_LIMIT_REG, limit)
+    }
+
+    /// Starts continuous conversions in latched window mode
+    pub fn enable_window(&self) -> ReturnCode {
+        match self.state.get() {
+            State::Monitoring => return ReturnCode::SUCCESS,
+            State::Converting => return ReturnCode::EBUSY,
+            State::Idle => {}
+        }
+
+        let result = self.write_register(OPT3001_LOW_LIMIT_REG, self.low_limit.get())
+            .and_then(|_| self.write_register(OPT3001_HIGH_LIMIT_REG, self.high_limit.get()))
+            .and_then(|_| self.write_register(OPT3001_CONFIG_REG, self.window_config()));
+        if let Err(err) = result {
+            return err.into();
+        }
+
+        self.state.set(State::Monitoring);
+        self.outside.set(None);
+
+        match self.int_pin {
+            Some(pin) => {
+                // INT is open drain and active low
+                pin.make_input();
+                pin.set_input_mode(gpio::InputMode::PullUp);
+                pin.enable_interrupt(0, gpio::InterruptMode::FallingEdge);
+                self.polling.set(false);
+            }
+            None => self.poll(),
+        }
+        ReturnCode::SUCCESS
+    }
+
+    pub fn disable_window(&self) -> ReturnCode {
+        if self.state.get() != State::Monitoring {
+            return ReturnCode::SUCCESS;
+        }
+
+        self.int_pin.map(|pin| pin.disable_interrupt());
+        self.alarm.disable();
+        self.polling.set(false);
+        self.state.set(State::Idle);
+
+        let result = self.write_register(OPT3001_CONFIG_REG, self.config(OPT3001_CONFIG_MODE_SHUTDOWN));
+
+        // A reading waiting on the window falls back to a single conversion
+        if self.light_pending.get() {
+            self.light_pending.set(false);
+            return self.read_light_intensity();
+        }
+
+        match result {
+            Ok(()) => ReturnCode::SUCCESS,
+            Err(err) => err.into(),
+        }
+    }
+
+    fn config(&self, mode: u16) -> u16 {
+        self.range.get().config_bits() | self.conversion_time.get().config_bits() | mode
+    }
+
+    fn window_config(&self) -> u16 {
+        self.config(OPT3001_CONFIG_MODE_CONTINUOUS) | OPT3001_CONFIG_L
+    }
+
+    fn identify(&self) -> Result<bool, I2cError> {
+        let manufacturer = self.read_register(OPT3001_MANUFACTURER_ID_REG)?;
+        let device = self.read_register(OPT3001_DEVICE_ID_REG)?;
+        Ok(manufacturer == OPT3001_MANUFACTURER_ID && device == OPT3001_DEVICE_ID)
+    }
+
+    fn read_register(&self, reg: u8) -> Result<u16, I2cError> {
+        let sensor = self.sensor.get();
+        let mut buf = [0; 2];
+
+        sensor.select();
+        let result = sensor.read_from_reg(reg, &mut buf, 2);
+        sensor.deselect();
+        result?;
+
+        Ok((buf[0] as u16) << 8 | (buf[1] as u16))
+    }
+
+    fn write_register(&self, reg: u8, value: u16) -> Result<(), I2cError> {
+        let sensor = self.sensor.get();
+        let buf = [(value >> 8) as u8, value as u8];
+
+        sensor.select();
+        let result = sensor.write_to_reg(reg, &buf);
+        sensor.deselect();
+        result
+    }
+
+    fn set_alarm_ms(&self, ms: u32) {
+        let ticks = rtc::ticks_from_ms::<A::Frequency>(ms);
+        self.alarm.set_alarm(self.alarm.now().wrapping_add(ticks));
+    }
+
+    fn start_conversion(&self) -> Result<(), I2cError> {
+        self.write_register(OPT3001_CONFIG_REG, self.config(OPT3001_CONFIG_MODE_SINGLE))?;
+
+        self.state.set(State::Converting);
+        self.retries.set(0);
+        self.set_alarm_ms(self.conversion_time.get().duration_ms() + OPT3001_CONVERSION_MARGIN_MS);
+        Ok(())
+    }
+
+    // Returns the raw result once the conversion has completed
+    fn read_result(&self) -> Result<Option<u16>, I2cError> {
+        let config = self.read_register(OPT3001_CONFIG_REG)?;
+        if config & OPT3001_CONFIG_CRF == 0 {
+            return Ok(None);
+        }
+        self.read_register(OPT3001_RESULT_REG).map(Some)
+    }
+
+    fn poll(&self) {
+        self.polling.set(true);
+        self.set_alarm_ms(self.conversion_time.get().duration_ms());
+    }
+
+    /// Reads the flags, which also clears them and releases INT, and
+    /// notifies when the light level has left the window
+    fn check_window(&self) -> Result<(), I2cError> {
+        let config = self.read_register(OPT3001_CONFIG_REG)?;
+        let raw = self.read_register(OPT3001_RESULT_REG)?;
+
+        let limit = if config & OPT3001_CONFIG_FH != 0 {
+            Some(Limit::High)
+        } else if config & OPT3001_CONFIG_FL != 0 {
+            Some(Limit::Low)
+        } else {
+            None
+        };
+
+        if let Some(left) = limit {
+            if self.outside.get() != limit {
+                self.window_client
+                    .get()
+                    .map(|client| client.left_window(left, convert_to_lux(raw)));
+            }
+        }
+        self.outside.set(limit);
+
+        if self.light_pending.get() {
+            self.light_pending.set(false);
+            self.client
+                .get()
+                .map(|client| client.callback(convert_to_lux(raw) as usize));
+        }
+        Ok(())
+    }
+
+    fn monitor(&self) {
+        self.polling.set(false);
+        if self.check_window().is_err() && self.light_pending.get() {
+            self.light_pending.set(false);
+            self.client
+                .get()
+                .map(|client| client.callback(sensor::READING_FAILED));
+        }
+
+        // Keep polling until the light level is back inside the window
+        if self.int_pin.is_none() || self.outside.get().is_some() {
+            self.poll();
+        }
+    }
+}
+
+/// Light level in hundredths of a lux: 2^exponent * mantissa
+pub fn convert_to_centilux(raw: u16) -> u32 {
+    let exponent = raw >> OPT3001_EXPONENT_SHIFT;
+    let mantissa = (raw & OPT3001_MANTISSA_MASK) as u32;
+    mantissa << exponent
+}
+
+/// Light level in lux, rounded to the nearest lux
+pub fn convert_to_lux(raw: u16) -> u32 {
+    (convert_to_centilux(raw) + 50) / 100
+}
+
+/// Limits use the result format, with the smallest exponent that fits
+pub fn convert_to_limit(centilux: u32) -> u16 {
+    let mut exponent = 0;
+    while exponent < OPT3001_MAX_EXPONENT && centilux >> exponent > OPT3001_MANTISSA_MASK as u32 {
+        exponent += 1;
+    }
+
+    let mut mantissa = centilux >> exponent;
+    if mantissa > OPT3001_MANTISSA_MASK as u32 {
+        mantissa = OPT3001_MANTISSA_MASK as u32;
+    }
+    exponent << OPT3001_EXPONENT_SHIFT | mantissa as u16
+}
+
+impl<'a, B: RegisterBus + 'a, A: Alarm + 'a, P: gpio::Pin + gpio::PinCtl + 'a> time::Client
+    for OPT3001<'a, B, A, P>
+{
+    fn fired(&self) {
+        if self.state.get() == State::Monitoring {
+            self.monitor();
+            return;
+        }
+
+        match self.read_result() {
+            Ok(Some(raw)) => {
+                self.state.set(State::Idle);
+                self.client
+                    .get()
+                    .map(|client| client.callback(convert_to_lux(raw) as usize));
+            }
+            Ok(None) | Err(_) => {
+                let retries = self.retries.get() + 1;
+                self.retries.set(retries);
+                if retries > OPT3001_MAX_RETRIES {
+                    self.state.set(State::Idle);
+                    self.client
+                        .get()
+                        .map(|client| client.callback(sensor::READING_FAILED));
+                } else {
+                    self.set_alarm_ms(OPT3001_RETRY_MS);
+                }
+            }
+        }
+    }
+}
+
+impl<'a, B: RegisterBus + 'a, A: Alarm + 'a, P: gpio::Pin + gpio::PinCtl + 'a> gpio::Client
+    for OPT3001<'a, B, A, P>
+{
+    fn fired(&self, _: usize) {
+        if self.state.get() != State::Monitoring || self.polling.get() {
+            return;
+        }
+        self.monitor();
+    }
+}
+
+impl<'a, B: RegisterBus + 'a, A: Alarm + 'a, P: gpio::Pin + gpio::PinCtl + 'a> AmbientLight
+    for OPT3001<'a, B, A, P>
+{
+    fn set_client(&self, client: &'static AmbientLightClient) {
+        self.client.set(Some(client));
+    }
+
+    fn read_light_intensity(&self) -> ReturnCode {
+        if !self.present.get() {
+            return ReturnCode::ENODEVICE;
+        }
+
+        match self.state.get() {
+            // The running conversion serves this request as well
+            State::Converting => ReturnCode::SUCCESS,
+            State::Monitoring => {
+                // Served from the continuous result with the next check
+                self.light_pending.set(true);
+                if !self.polling.get() {
+                    self.polling.set(true);
+                    self.set_alarm_ms(OPT3001_READ_DELAY_MS);
+                }
+                ReturnCode::SUCCESS
+            }
+            State::Idle => match self.start_conversion() {
+                Ok(()) => ReturnCode::SUCCESS,
+                Err(err) => err.into(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sim_bus::{SimulatedAlarm, SimulatedBus, SimulatedPin};
+
+    type Opt3001<'a> = OPT3001<'a, SimulatedBus, SimulatedAlarm, SimulatedPin>;
+
+    struct Events {
+        lux: Cell<Option<usize>>,
+        left: Cell<Option<(Limit, u32)>>,
+        notifications: Cell<usize>,
+    }
+
+    impl AmbientLightClient for Events {
+        fn callback(&self, lux: usize) {
+            self.lux.set(Some(lux));
+        }
+    }
+
+    impl Opt3001Client for Events {
+        fn left_window(&self, limit: Limit, lux: u32) {
+            self.left.set(Some((limit, lux)));
+            self.notifications.set(self.notifications.get() + 1);
+        }
+    }
+
+    fn device() -> SimulatedBus {
+        let bus = SimulatedBus::new(OPT3001_ADDRESS, 2);
+        bus.set_register(OPT3001_MANUFACTURER_ID_REG, OPT3001_MANUFACTURER_ID);
+        bus.set_register(OPT3001_DEVICE_ID_REG, OPT3001_DEVICE_ID);
+        bus
+    }
+
+    fn subscribe(opt: &Opt3001) -> &'static Events {
+        let events: &'static Events = Box::leak(Box::new(Events {
+            lux: Cell::new(None),
+            left: Cell::new(None),
+            notifications: Cell::new(0),
+        }));
+        AmbientLight::set_client(opt, events);
+        opt.set_window_client(events);
+        events
+    }
+
+    // Lets the alarm in progress fire
+    fn expire(opt: &Opt3001, alarm: &SimulatedAlarm) {
+        assert!(alarm.expire());
+        time::Client::fired(opt);
+    }
+
+    // Completes the single-shot conversion in progress with `raw`
+    fn complete(bus: &SimulatedBus, raw: u16) {
+        let config = bus.register(OPT3001_CONFIG_REG);
+        bus.set_register(OPT3001_CONFIG_REG, config | OPT3001_CONFIG_CRF);
+        bus.set_register(OPT3001_RESULT_REG, raw);
+    }
+
+    #[test]
+    fn light_conversion() {
+        assert_eq!(convert_to_centilux(0), 0);
+        // 2^1 * 50 hundredths
+        assert_eq!(convert_to_centilux(0x1032), 100);
+        assert_eq!(convert_to_lux(0x1032), 1);
+        // Rounded to the nearest lux
+        assert_eq!(convert_to_lux(0x0031), 0);
+        assert_eq!(convert_to_lux(0x0032), 1);
+        // Full-scale, 83865.6 lux
+        assert_eq!(convert_to_centilux(0xBFFF), 8386560);
+        assert_eq!(convert_to_lux(0xBFFF), 83866);
+    }
+
+    #[test]
+    fn limits_use_the_smallest_exponent() {
+        assert_eq!(convert_to_limit(0), 0);
+        assert_eq!(convert_to_limit(4095), 0x0FFF);
+        assert_eq!(convert_to_limit(4096), 0x1800);
+        assert_eq!(convert_to_centilux(convert_to_limit(100000)), 100000);
+        // Saturates at full-scale
+        assert_eq!(convert_to_limit(u32::max_value()), 0xBFFF);
+    }
+
+    #[test]
+    fn init_rejects_other_devices() {
+        let bus = device();
+        bus.set_register(OPT3001_DEVICE_ID_REG, 0x3002);
+        let alarm = SimulatedAlarm::new();
+        let opt: Opt3001 = OPT3001::new(&bus, &alarm, None);
+
+        assert_eq!(opt.init(), ReturnCode::ENODEVICE);
+        assert_eq!(opt.read_light_intensity(), ReturnCode::ENODEVICE);
+        assert!(!alarm.is_armed());
+    }
+
+    #[test]
+    fn conversions_use_automatic_ranging() {
+        let bus = device();
+        let alarm = SimulatedAlarm::new();
+        let opt: Opt3001 = OPT3001::new(&bus, &alarm, None);
+
+        assert_eq!(opt.init(), ReturnCode::SUCCESS);
+        assert_eq!(
+            bus.register(OPT3001_CONFIG_REG),
+            OPT3001_CONFIG_RN_AUTO | OPT3001_CONFIG_MODE_SHUTDOWN
+        );
+
+        assert_eq!(opt.read_light_intensity(), ReturnCode::SUCCESS);
+        assert_eq!(
+            bus.register(OPT3001_CONFIG_REG),
+            OPT3001_CONFIG_RN_AUTO | OPT3001_CONFIG_MODE_SINGLE
+        );
+    }
+
+    #[test]
+    fn manual_range_applies_to_the_next_conversion() {
+        let bus = device();
+        let alarm = SimulatedAlarm::new();
+        let opt: Opt3001 = OPT3001::new(&bus, &alarm, None);
+        assert_eq!(opt.init(), ReturnCode::SUCCESS);
+
+        opt.set_range(Range::Manual(3));
+        opt.set_conversion_time(ConversionTime::Ms800);
+        assert_eq!(opt.read_light_intensity(), ReturnCode::SUCCESS);
+        assert_eq!(
+            bus.register(OPT3001_CONFIG_REG),
+            3 << OPT3001_CONFIG_RN_SHIFT | OPT3001_CONFIG_CT | OPT3001_CONFIG_MODE_SINGLE
+        );
+    }
+
+    #[test]
+    fn conversion_delivers_lux() {
+        let bus = device();
+        let alarm = SimulatedAlarm::new();
+        let opt: Opt3001 = OPT3001::new(&bus, &alarm, None);
+        assert_eq!(opt.init(), ReturnCode::SUCCESS);
+        let events = subscribe(&opt);
+
+        assert_eq!(opt.read_light_intensity(), ReturnCode::SUCCESS);
+        // A second request is served by the same conversion
+        assert_eq!(opt.read_light_intensity(), ReturnCode::SUCCESS);
+        complete(&bus, 0x3FA0);
+        expire(&opt, &alarm);
+
+        // 2^3 * 4000 hundredths
+        assert_eq!(events.lux.get(), Some(320));
+        assert!(!alarm.is_armed());
+    }
+
+    #[test]
+    fn unfinished_conversion_is_polled_again() {
+        let bus = device();
+        let alarm = SimulatedAlarm::new();
+        let opt: Opt3001 = OPT3001::new(&bus, &alarm, None);
+        assert_eq!(opt.init(), ReturnCode::SUCCESS);
+        let events = subscribe(&opt);
+
+        assert_eq!(opt.read_light_intensity(), ReturnCode::SUCCESS);
+        expire(&opt, &alarm);
+        assert_eq!(events.lux.get(), None);
+
+        complete(&bus, 0x0064);
+        expire(&opt, &alarm);
+        assert_eq!(events.lux.get(), Some(1));
+    }
+
+    #[test]
+    fn readout_gives_up_after_the_retries() {
+        let bus = device();
+        let alarm = SimulatedAlarm::new();
+        let opt: Opt3001 = OPT3001::new(&bus, &alarm, None);
+        assert_eq!(opt.init(), ReturnCode::SUCCESS);
+        let events = subscribe(&opt);
+
+        assert_eq!(opt.read_light_intensity(), ReturnCode::SUCCESS);
+        for _ in 0..(OPT3001_MAX_RETRIES + 1) {
+            bus.fail_next(I2cError::DataNack);
+            expire(&opt, &alarm);
+        }
+        assert!(!alarm.is_armed());
+        assert_eq!(events.lux.get(), Some(sensor::READING_FAILED));
+
+        // The next request starts over
+        assert_eq!(opt.read_light_intensity(), ReturnCode::SUCCESS);
+        assert!(alarm.is_armed());
+    }
+
+    #[test]
+    fn window_runs_in_latched_continuous_mode() {
+        let bus = device();
+        let alarm = SimulatedAlarm::new();
+        let pin = SimulatedPin::new();
+        let opt: Opt3001 = OPT3001::new(&bus, &alarm, Some(&pin));
+        assert_eq!(opt.init(), ReturnCode::SUCCESS);
+
+        opt.set_low_limit(1000).unwrap();
+        opt.set_high_limit(50000).unwrap();
+        assert_eq!(opt.enable_window(), ReturnCode::SUCCESS);
+
+        assert_eq!(bus.register(OPT3001_LOW_LIMIT_REG), convert_to_limit(1000));
+        assert_eq!(bus.register(OPT3001_HIGH_LIMIT_REG), convert_to_limit(50000));
+        assert_eq!(
+            bus.register(OPT3001_CONFIG_REG),
+            OPT3001_CONFIG_RN_AUTO | OPT3001_CONFIG_MODE_CONTINUOUS | OPT3001_CONFIG_L
+        );
+        // INT signals the flags, no polling needed
+        assert!(pin.interrupt_enabled());
+        assert!(!alarm.is_armed());
+        // Enabling again leaves the window running
+        assert_eq!(opt.enable_window(), ReturnCode::SUCCESS);
+
+        assert_eq!(opt.disable_window(), ReturnCode::SUCCESS);
+        assert!(!pin.interrupt_enabled());
+        assert_eq!(
+            bus.register(OPT3001_CONFIG_REG),
+            OPT3001_CONFIG_RN_AUTO | OPT3001_CONFIG_MODE_SHUTDOWN
+        );
+    }
+
+    #[test]
+    fn leaving_the_window_is_notified_once() {
+        let bus = device();
+        let alarm = SimulatedAlarm::new();
+        let pin = SimulatedPin::new();
+        let opt: Opt3001 = OPT3001::new(&bus, &alarm, Some(&pin));
+        assert_eq!(opt.init(), ReturnCode::SUCCESS);
+        let events = subscribe(&opt);
+        assert_eq!(opt.enable_window(), ReturnCode::SUCCESS);
+
+        let config = bus.register(OPT3001_CONFIG_REG);
+        bus.set_register(OPT3001_CONFIG_REG, config | OPT3001_CONFIG_FH);
+        bus.set_register(OPT3001_RESULT_REG, 0x3FA0);
+        gpio::Client::fired(&opt, 0);
+        assert_eq!(events.left.get(), Some((Limit::High, 320)));
+
+        // Polled while outside, without notifying again
+        expire(&opt, &alarm);
+        assert_eq!(events.notifications.get(), 1);
+
+        // Back inside the window, INT takes over again
+        bus.set_register(OPT3001_CONFIG_REG, config);
+        expire(&opt, &alarm);
+        assert!(!alarm.is_armed());
+
+        bus.set_register(OPT3001_CONFIG_REG, config | OPT3001_CONFIG_FL);
+        bus.set_register(OPT3001_RESULT_REG, 0x0032);
+        gpio::Client::fired(&opt, 0);
+        assert_eq!(events.left.get(), Some((Limit::Low, 1)));
+        assert_eq!(events.notifications.get(), 2);
+    }
+
+    #[test]
+    fn readings_are_served_from_the_window() {
+        let bus = device();
+        let alarm = SimulatedAlarm::new();
+        let opt: Opt3001 = OPT3001::new(&bus, &alarm, None);
+        assert_eq!(opt.init(), ReturnCode::SUCCESS);
+        let events = subscribe(&opt);
+        assert_eq!(opt.enable_window(), ReturnCode::SUCCESS);
+
+        // Without INT the flags are polled all along
+        assert!(alarm.is_armed());
+        bus.set_register(OPT3001_RESULT_REG, 0x0064);
+        assert_eq!(opt.read_light_intensity(), ReturnCode::SUCCESS);
+        expire(&opt, &alarm);
+        assert_eq!(events.lux.get(), Some(1));
+        assert_eq!(events.left.get(), None);
+        assert!(alarm.is_armed());
+
+        assert_eq!(opt.read_light_intensity(), ReturnCode::SUCCESS);
+        bus.fail_next(I2cError::DataNack);
+        expire(&opt, &alarm);
+        assert_eq!(events.lux.get(), Some(sensor::READING_FAILED));
+        assert!(alarm.is_armed());
+    }
+}
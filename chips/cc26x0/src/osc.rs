@@ -34,6 +34,11 @@ pub enum ClockType {
 pub const HF_RCOSC: u8 = 0x00;
 pub const HF_XOSC: u8 = 0x01;
 
+// Frequency of SCLK_HF for each of its sources. The XOSC runs either from a
+// 48MHz crystal or from a 24MHz one doubled internally.
+pub const HF_RCOSC_FREQ: u32 = 48_000_000;
+pub const HF_XOSC_FREQ: u32 = 48_000_000;
+
 pub const HF_STAT0_MASK: u32 = 0x10000000;
 pub const LF_STAT0_MASK: u32 = 0x60000000;
 
@@ -107,6 +112,14 @@ impl Oscillator {
         }
     }
 
+    /// Frequency of SCLK_HF from the source currently driving it
+    pub fn sclk_hf_freq(&self) -> u32 {
+        match self.clock_source_get(ClockType::HF) {
+            HF_XOSC => HF_XOSC_FREQ,
+            _ => HF_RCOSC_FREQ,
+        }
+    }
+
     pub fn clock_source_get(&self, clock: ClockType) -> u8 {
         let regs: &DdiRegisters = unsafe { &*self.r_regs };
         match clock {
@@ -45,7 +45,12 @@ struct PrcmRegisters {
     pub uart_clk_gate_sleep: ReadWrite<u32, ClockGate::Register>,
     pub uart_clk_gate_deep_sleep: ReadWrite<u32, ClockGate::Register>,
 
-    _reserved4: [ReadOnly<u8>; 0xB4],
+    _reserved4: [ReadOnly<u8>; 0x40],
+
+    // Divides SCLK_HF into the clock of the CPU and the bus peripherals
+    pub cpu_clk_div: ReadWrite<u32, ClockDivider::Register>,
+
+    _reserved5: [ReadOnly<u8>; 0x70],
 
     // Power domain control 0
     pub pd_ctl0: ReadWrite<u32, PowerDomain0::Register>,
@@ -53,7 +58,7 @@ struct PrcmRegisters {
     pub pd_ctl0_serial: WriteOnly<u32, PowerDomainSingle::Register>,
     pub pd_ctl0_peripheral: WriteOnly<u32, PowerDomainSingle::Register>,
 
-    _reserved5: [ReadOnly<u8>; 0x04],
+    _reserved6: [ReadOnly<u8>; 0x04],
 
     // Power domain status 0
     pub pd_stat0: ReadOnly<u32, PowerDomainStatus0::Register>,
@@ -88,6 +93,12 @@ register_bitfields![
     ClockGate [
         CLK_EN  OFFSET(0) NUMBITS(1) []
     ],
+    ClockDivider [
+        RATIO   OFFSET(0) NUMBITS(1) [
+            DivideBy1 = 0,
+            DivideBy2 = 1
+        ]
+    ],
     PowerDomain0 [
         PERIPH_ON   OFFSET(2) NUMBITS(1) [],
         SERIAL_ON   OFFSET(1) NUMBITS(1) [],
@@ -227,6 +238,12 @@ impl Clock {
         let regs: &PrcmRegisters = unsafe { &*PRCM_BASE };
         regs.i2c_clk_gate_run.is_set(ClockGate::CLK_EN)
     }
+
+    /// Factor SCLK_HF is divided by for the CPU and the bus peripherals
+    pub fn cpu_clock_divider() -> u32 {
+        let regs: &PrcmRegisters = unsafe { &*PRCM_BASE };
+        1 << regs.cpu_clk_div.read(ClockDivider::RATIO)
+    }
 }
 
 pub fn rf_mode_sel(mode: u32) {
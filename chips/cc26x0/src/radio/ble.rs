@@ -32,11 +32,16 @@ static mut BLE_ADV_PAYLOAD: [u8; 64] = [0; 64];
 static mut BLE_ADV_PAYLOAD_LEN: u8 = 0;
 static mut PACKET_BUF: [u8; 128] = [0; 128];
 static mut DEVICE_ADDRESS: [u8; 6] = [0; 6];
+static mut NOP_BUF: [u32; 4] = [0; 4];
 
 pub struct Ble {
     rfc: &'static rfc::RFCore,
     rx_client: Cell<Option<&'static ble_advertising::RxClient>>,
     tx_client: Cell<Option<&'static ble_advertising::TxClient>>,
+    advertising_divider: Cell<usize>,
+    advertising_events: Cell<usize>,
+    skip_event: Cell<bool>,
+    skipping: Cell<bool>,
 }
 
 #[allow(unused)]
@@ -61,9 +66,24 @@ impl Ble {
             rfc,
             rx_client: Cell::new(None),
             tx_client: Cell::new(None),
+            advertising_divider: Cell::new(1),
+            advertising_events: Cell::new(0),
+            skip_event: Cell::new(false),
+            skipping: Cell::new(false),
         }
     }
 
+    /// Transmits only one in every `divider` advertising events, to save
+    /// power. The other events are skipped on all channels.
+    pub fn set_advertising_divider(&self, divider: usize) -> kernel::ReturnCode {
+        if divider == 0 {
+            return kernel::ReturnCode::EINVAL;
+        }
+        self.advertising_divider.set(divider);
+        self.advertising_events.set(0);
+        kernel::ReturnCode::SUCCESS
+    }
+
     pub fn configure(&self) {
         if self.rfc.current_mode() == Some(rfc::RfcMode::BLE) {
             return
@@ -155,6 +175,39 @@ impl Ble {
         buf
     }
 
+    /*
+        A skipped advertisement runs a NOP on the radio instead, so that the
+        transmit client is still called back from the radio interrupt.
+        Returns false if the radio refused the NOP, in which case the
+        advertisement has to be sent after all.
+    */
+    fn skip_advertisement(&self) -> bool {
+        self.configure();
+        self.skipping.set(true);
+
+        unsafe {
+            for i in 0..NOP_BUF.len() {
+                NOP_BUF[i] = 0;
+            }
+
+            let cmd: &mut rfc_commands::RfcCommandNop = &mut *(NOP_BUF.as_mut_ptr() as *mut rfc_commands::RfcCommandNop);
+            cmd.command_no = rfc::RFC_NOP;
+            cmd.condition = {
+                let mut cnd = rfc_commands::RfcCondition(0);
+                cnd.set_rule(1); // COND_NEVER
+                cnd
+            };
+            match self.rfc.send(cmd) {
+                Err(status) => {
+                    debug!("Could not skip advertisement, status=0x{:x}\r", status);
+                    self.skipping.set(false);
+                    false
+                }
+                Ok(()) => true
+            }
+        }
+    }
+
     pub fn advertise(&self, radio_channel: RadioChannel) {
         self.configure();
 
@@ -179,6 +232,12 @@ impl Ble {
 
 impl rfc::RFCoreClient for Ble {
     fn command_done(&self) {
+        if self.skipping.get() {
+            self.skipping.set(false);
+            self.tx_client
+                .get()
+                .map(|client| client.transmit_event(kernel::ReturnCode::SUCCESS));
+        }
     }
 
     fn tx_done(&self) {
@@ -195,6 +254,17 @@ impl ble_advertising::BleAdvertisementDriver for Ble {
         len: usize,
         channel: RadioChannel,
     ) -> &'static mut [u8] {
+        // Every advertising event starts on channel 37
+        if let RadioChannel::AdvertisingChannel37 = channel {
+            let events = (self.advertising_events.get() + 1) % self.advertising_divider.get();
+            self.advertising_events.set(events);
+            self.skip_event.set(events != 0);
+        }
+
+        if self.skip_event.get() && self.skip_advertisement() {
+            return buf;
+        }
+
         let res = unsafe { self.replace_adv_payload_buffer(buf, len) };
         self.advertise(channel);
         res
@@ -93,6 +93,7 @@ const RFC_PING: u16 = 0x406;
 const RFC_BUS_REQUEST: u16 = 0x40E;
 const RFC_START_RAT_TIMER: u16 = 0x080A;
 const RFC_SETUP: u16 = 0x0802;
+pub const RFC_NOP: u16 = 0x0801;
 
 /*
     Power masks in order to enable certain clocks in the RFC
@@ -480,6 +481,17 @@ pub mod rfc_commands {
         pub rat0: u32,
     }
 
+    /* Does nothing, but completes like any other radio operation */
+    #[repr(C)]
+    pub struct RfcCommandNop {
+        pub command_no: u16,
+        pub status: u16,
+        pub p_nextop: u32,
+        pub ratmr: u32,
+        pub start_trigger: u8,
+        pub condition: RfcCondition,
+    }
+
     /* Bitfields used by many commands */
     bitfield! {
         #[derive(Copy, Clone)]
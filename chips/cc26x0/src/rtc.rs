@@ -2,7 +2,7 @@
 
 use core::cell::Cell;
 use kernel::common::regs::{ReadOnly, ReadWrite};
-use kernel::hil::time::{self, Alarm, Freq32KHz, Time};
+use kernel::hil::time::{self, Alarm, Frequency, Time};
 
 #[repr(C)]
 pub struct RtcRegisters {
@@ -58,6 +58,32 @@ register_bitfields![
 
 const RTC_BASE: *const RtcRegisters = 0x4009_2000 as *const RtcRegisters;
 
+// The counter returned by `now` holds seconds in 16.16 fixed point
+pub const RTC_TICKS_PER_SEC: u64 = 1 << 16;
+// It is clocked at 32kHz though, so it advances two ticks (~30.5us) at a time
+pub const RTC_TICKS_PER_EDGE: u32 = 2;
+
+/// Frequency of the counter returned by `now`
+pub struct Freq65KHz;
+
+impl Frequency for Freq65KHz {
+    fn frequency() -> u32 {
+        RTC_TICKS_PER_SEC as u32
+    }
+}
+
+/// Converts microseconds into ticks of an alarm running at `F`, rounding up
+/// so that alarms never fire early.
+pub fn ticks_from_us<F: Frequency>(us: u32) -> u32 {
+    ((us as u64 * F::frequency() as u64 + 999_999) / 1_000_000) as u32
+}
+
+/// Converts milliseconds into ticks of an alarm running at `F`, rounding up
+/// so that alarms never fire early.
+pub fn ticks_from_ms<F: Frequency>(ms: u32) -> u32 {
+    ((ms as u64 * F::frequency() as u64 + 999) / 1000) as u32
+}
+
 pub struct Rtc {
     regs: *const RtcRegisters,
     callback: Cell<Option<&'static time::Client>>,
@@ -106,6 +132,11 @@ impl Rtc {
         return (current_sec << 16) | (current_subsec >> 16);
     }
 
+    pub fn is_enabled(&self) -> bool {
+        let regs: &RtcRegisters = unsafe { &*self.regs };
+        regs.ctl.is_set(Control::ENABLE)
+    }
+
     pub fn is_running(&self) -> bool {
         let regs: &RtcRegisters = unsafe { &*self.regs };
         regs.channel_ctl.read(ChannelControl::CH1_EN) != 0
@@ -139,7 +170,12 @@ impl Rtc {
 }
 
 impl Time for Rtc {
-    type Frequency = Freq32KHz;
+    // `now` counts 1/65536 s even though the counter is clocked at 32kHz, see
+    // `read_counter`. Every user of the alarms converts times with this
+    // frequency: the userspace alarm driver reports it to applications, the
+    // BLE advertising driver derives its interval from it and the sensor
+    // drivers go through `ticks_from_us` and `ticks_from_ms`.
+    type Frequency = Freq65KHz;
 
     fn disable(&self) {
         let regs: &RtcRegisters = unsafe { &*self.regs };
@@ -175,3 +211,90 @@ impl Alarm for Rtc {
         regs.channel1_cmp.get()
     }
 }
+
+/// A point in time measured against the RTC, used to bound busy waits
+/// independently of the clock speed and compiler optimisations.
+#[derive(Copy, Clone)]
+pub struct Deadline {
+    start: u32,
+    ticks: u32,
+}
+
+impl Deadline {
+    pub fn from_us(us: u32) -> Deadline {
+        let rtc = unsafe { &RTC };
+        if !rtc.is_enabled() {
+            rtc.start();
+        }
+
+        Deadline {
+            start: rtc.now(),
+            ticks: deadline_ticks(us),
+        }
+    }
+
+    pub fn from_ms(ms: u32) -> Deadline {
+        Deadline::from_us(ms.saturating_mul(1000))
+    }
+
+    pub fn expired(&self) -> bool {
+        let rtc = unsafe { &RTC };
+        rtc.now().wrapping_sub(self.start) >= self.ticks
+    }
+}
+
+// Ticks to wait for at least `us` microseconds, in whole edges of the 32kHz
+// clock plus one since `start` may be read just before the counter advances
+fn deadline_ticks(us: u32) -> u32 {
+    let edges = (ticks_from_us::<Freq65KHz>(us) + RTC_TICKS_PER_EDGE - 1) / RTC_TICKS_PER_EDGE;
+    (edges + 1) * RTC_TICKS_PER_EDGE
+}
+
+/// Busy waits for (at least) the given amount of microseconds.
+/// The resolution is one period of the 32kHz clock, ~30.5us.
+pub fn delay_us(us: u32) {
+    let deadline = Deadline::from_us(us);
+    while !deadline.expired() {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kernel::hil::time::Freq32KHz;
+
+    #[test]
+    fn alarms_run_at_the_counter_frequency() {
+        assert_eq!(ticks_from_ms::<Freq65KHz>(1000), RTC_TICKS_PER_SEC as u32);
+        assert_eq!(ticks_from_us::<Freq65KHz>(500_000), RTC_TICKS_PER_SEC as u32 / 2);
+        assert_eq!(ticks_from_ms::<Freq32KHz>(1000), 32768);
+    }
+
+    #[test]
+    fn ticks_are_rounded_up() {
+        assert_eq!(ticks_from_us::<Freq65KHz>(0), 0);
+        assert_eq!(ticks_from_us::<Freq65KHz>(1), 1);
+        assert_eq!(ticks_from_us::<Freq65KHz>(16), 2);
+        assert_eq!(ticks_from_ms::<Freq65KHz>(1), 66);
+    }
+
+    #[test]
+    fn deadlines_wait_for_whole_edges() {
+        // Starting just before an edge, the first one passes right away
+        assert_eq!(deadline_ticks(0), 2);
+        assert_eq!(deadline_ticks(5), 4);
+        assert_eq!(deadline_ticks(30), 4);
+        assert_eq!(deadline_ticks(31), 6);
+        assert_eq!(deadline_ticks(1000), 68);
+    }
+
+    #[test]
+    fn deadlines_are_never_shorter_than_requested() {
+        for &us in [1, 5, 15, 16, 31, 61, 100, 999, 25_000].iter() {
+            let ticks = deadline_ticks(us);
+            assert_eq!(ticks % RTC_TICKS_PER_EDGE, 0);
+            // Only the edges after the first one are guaranteed to be waited for
+            let waited_us = (ticks - RTC_TICKS_PER_EDGE) as u64 * 1_000_000 / RTC_TICKS_PER_SEC;
+            assert!(waited_us + 1 >= us as u64, "{} us waits {} us", us, waited_us);
+        }
+    }
+}
@@ -1,57 +1,181 @@
-use i2c;
+use i2c::{self, I2cError, I2cInterface, I2cSpeed};
 
-pub const BUFFER_SIZE: usize = 32;
+/// Register level access to the bus the sensors are attached to.
+///
+/// The CC2650 I2C controller is the implementation used on the board,
+/// `sim_bus::SimulatedBus` provides an in-memory device for running the
+/// sensor drivers in the unit tests, without hardware.
+pub trait RegisterBus {
+    fn select(&self, interface: I2cInterface, address: u8, speed: I2cSpeed);
+    fn deselect(&self);
+    fn read(&self, buf: &mut [u8], len: u8) -> Result<(), I2cError>;
+    fn write(&self, buf: &[u8], len: u8) -> Result<(), I2cError>;
+    fn write_read(&self, buf: &mut [u8], write_len: u8, read_len: u8) -> Result<(), I2cError>;
+    fn write_prefixed(&self, prefix: u8, data: &[u8]) -> Result<(), I2cError>;
+    fn write_single(&self, data: u8) -> Result<(), I2cError>;
+}
+
+impl RegisterBus for i2c::I2C {
+    fn select(&self, interface: I2cInterface, address: u8, speed: I2cSpeed) {
+        i2c::I2C::select(self, interface, address, speed);
+    }
+
+    fn deselect(&self) {
+        i2c::I2C::select(self, I2cInterface::Interface0, 0, self.speed());
+    }
+
+    fn read(&self, buf: &mut [u8], len: u8) -> Result<(), I2cError> {
+        i2c::I2C::read(self, buf, len)
+    }
+
+    fn write(&self, buf: &[u8], len: u8) -> Result<(), I2cError> {
+        i2c::I2C::write(self, buf, len)
+    }
+
+    fn write_read(&self, buf: &mut [u8], write_len: u8, read_len: u8) -> Result<(), I2cError> {
+        i2c::I2C::write_read(self, buf, write_len, read_len)
+    }
 
-#[derive(Copy, Clone)]
-pub struct Sensor {
-    interface: i2c::I2cInterface,
+    fn write_prefixed(&self, prefix: u8, data: &[u8]) -> Result<(), I2cError> {
+        i2c::I2C::write_prefixed(self, prefix, data)
+    }
+
+    fn write_single(&self, data: u8) -> Result<(), I2cError> {
+        i2c::I2C::write_single(self, data)
+    }
+}
+
+/// Converts a raw reading that scales linearly with temperature into signed
+/// hundredths of a degree Celsius, computing `raw * num / den + offset`
+/// rounded to the nearest hundredth.
+///
+/// Every temperature producing driver goes through this, so that they all
+/// report in the same unit.
+pub fn centi_celsius(raw: i32, num: i32, den: i32, offset: i32) -> i32 {
+    let scaled = raw as i64 * num as i64;
+    let den = den as i64;
+    let rounded = if scaled >= 0 {
+        (scaled + den / 2) / den
+    } else {
+        (scaled - den / 2) / den
+    };
+    rounded as i32 + offset
+}
+
+/// Temperature clients receive the signed hundredths of a degree in a `usize`
+pub fn temperature_value(centi_celsius: i32) -> usize {
+    centi_celsius as isize as usize
+}
+
+/// Delivered to clients of the sensor HILs in place of a value when a
+/// reading has failed. As a signed value it is far outside the range of any
+/// of the sensors.
+pub const READING_FAILED: usize = isize::min_value() as usize;
+
+pub struct Sensor<'a, B: RegisterBus + 'a> {
+    bus: &'a B,
+    interface: I2cInterface,
     address: u8,
+    speed: I2cSpeed,
+}
+
+// Implemented by hand since deriving would require the bus to be `Copy`
+impl<'a, B: RegisterBus + 'a> Clone for Sensor<'a, B> {
+    fn clone(&self) -> Sensor<'a, B> {
+        *self
+    }
 }
 
-impl Sensor {
-    pub const fn new(interface: i2c::I2cInterface, address: u8) -> Sensor {
+impl<'a, B: RegisterBus + 'a> Copy for Sensor<'a, B> {}
+
+impl<'a, B: RegisterBus + 'a> Sensor<'a, B> {
+    pub const fn new(bus: &'a B, interface: I2cInterface, address: u8) -> Sensor<'a, B> {
+        Sensor::new_with_speed(bus, interface, address, I2cSpeed::Fast)
+    }
+
+    pub const fn new_with_speed(bus: &'a B, interface: I2cInterface, address: u8, speed: I2cSpeed) -> Sensor<'a, B> {
         Sensor {
+            bus,
             interface,
             address,
+            speed,
         }
     }
 
-    pub unsafe fn select(&self) {
-        i2c::I2C0.select(self.interface, self.address);
+    pub fn speed(&self) -> I2cSpeed {
+        self.speed
     }
 
-    pub unsafe fn deselect(&self) {
-        i2c::I2C0.select(i2c::I2cInterface::Interface0, 0);
+    pub fn set_speed(&mut self, speed: I2cSpeed) {
+        self.speed = speed;
     }
 
-    pub unsafe fn read(&self, buf: &mut [u8], len: u8) -> bool {
-        i2c::I2C0.read(buf, len)
+    pub fn select(&self) {
+        self.bus.select(self.interface, self.address, self.speed);
     }
 
-    pub unsafe fn write(&self, buf: & [u8], len: u8) -> bool {
-        i2c::I2C0.write(buf, len)
+    pub fn deselect(&self) {
+        self.bus.deselect();
     }
 
-    pub unsafe fn read_from_reg(&self, addr: u8, buf: &mut [u8], len: u8) -> bool {
+    pub fn read(&self, buf: &mut [u8], len: u8) -> Result<(), I2cError> {
+        self.bus.read(buf, len)
+    }
+
+    pub fn write(&self, buf: & [u8], len: u8) -> Result<(), I2cError> {
+        self.bus.write(buf, len)
+    }
+
+    pub fn read_from_reg(&self, addr: u8, buf: &mut [u8], len: u8) -> Result<(), I2cError> {
         buf[0] = addr;
-        i2c::I2C0.write_read(buf, 1, len)
-    }
-
-    pub unsafe fn write_to_reg(&self, addr: u8, buf: &mut [u8], len: u8) -> bool {
-        if len == 0 {
-            self.write_reg_address(addr)
-        } else {
-            let mut local_buf = [0; BUFFER_SIZE];
-            local_buf[0] = addr;
-            for i in 0..len {
-                local_buf[(i + 1) as usize] = buf[i as usize];
-            }
-            i2c::I2C0.write(&mut local_buf, len + 1)
-        }
+        self.bus.write_read(buf, 1, len)
+    }
+
+    /// Writes all of `data` to the registers starting at `addr`
+    pub fn write_to_reg(&self, addr: u8, data: &[u8]) -> Result<(), I2cError> {
+        self.bus.write_prefixed(addr, data)
+    }
+
+    pub fn write_reg_address(&self, addr: u8) -> Result<(), I2cError> {
+        self.bus.write_single(addr)
     }
 
-    pub unsafe fn write_reg_address(&self, addr: u8) -> bool {
-        i2c::I2C0.write_single(addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn centi_celsius_rounds_to_nearest() {
+        assert_eq!(centi_celsius(0, 1, 2, 0), 0);
+        assert_eq!(centi_celsius(1, 1, 3, 0), 0);
+        assert_eq!(centi_celsius(2, 1, 3, 0), 1);
+        // Halves round away from zero
+        assert_eq!(centi_celsius(1, 1, 2, 0), 1);
+        assert_eq!(centi_celsius(-1, 1, 2, 0), -1);
+        assert_eq!(centi_celsius(-2, 1, 3, 0), -1);
     }
 
+    #[test]
+    fn centi_celsius_below_zero() {
+        assert_eq!(centi_celsius(-3, 100, 1, 0), -300);
+        assert_eq!(centi_celsius(100, 1, 1, -4000), -3900);
+        assert_eq!(centi_celsius(-128, 3125, 1000, 0), -400);
+    }
+
+    #[test]
+    fn centi_celsius_does_not_overflow() {
+        assert_eq!(centi_celsius(0xFFFF, 16500, 65536, -4000), 12500);
+        assert_eq!(centi_celsius(-0x8000, 78125, 10000, 0), -256000);
+    }
+
+    #[test]
+    fn negative_temperatures_keep_their_sign() {
+        assert_eq!(temperature_value(0), 0);
+        assert_eq!(temperature_value(2150), 2150);
+        assert_eq!(temperature_value(-1), usize::max_value());
+        assert_eq!(temperature_value(-1000) as isize, -1000);
+        assert_eq!(temperature_value(-4000) as isize as i32, -4000);
+    }
 }
@@ -0,0 +1,275 @@
+//! Simulated register bus
+//!
+//! An in-memory stand-in for a single I2C device, implementing
+//! `sensor::RegisterBus` so that the sensor drivers can be exercised on the
+//! host without any hardware attached.
+//!
+//! The device behaves like most register based sensors: the first byte of a
+//! write sets the register pointer, the following bytes are stored starting
+//! at that register and reads return data from the register pointer onwards.
+//! Registers are either one or two bytes wide (big endian).
+//!
+//! `SimulatedAlarm` complements it for the alarm driven drivers: time only
+//! passes when a test expires the alarm. `SimulatedPin` stands in for the
+//! GPIO pins the drivers switch or take interrupts from.
+
+use core::cell::Cell;
+use i2c::{I2cError, I2cInterface, I2cSpeed};
+use kernel::hil::gpio;
+use kernel::hil::time::{Alarm, Time};
+use rtc::Freq65KHz;
+use sensor::RegisterBus;
+
+pub const SIM_REGISTERS: usize = 256;
+pub const SIM_MEMORY_SIZE: usize = SIM_REGISTERS * 2;
+
+pub struct SimulatedBus {
+    address: u8,
+    register_width: usize,
+    memory: Cell<[u8; SIM_MEMORY_SIZE]>,
+    pointer: Cell<usize>,
+    selected: Cell<Option<u8>>,
+    next_error: Cell<Option<I2cError>>,
+    // Transfers left before `next_error` is returned
+    error_after: Cell<usize>,
+}
+
+impl SimulatedBus {
+    /// Creates a device responding to `address` with registers of
+    /// `register_width` (1 or 2) bytes.
+    pub fn new(address: u8, register_width: usize) -> SimulatedBus {
+        SimulatedBus {
+            address,
+            register_width: if register_width == 2 { 2 } else { 1 },
+            memory: Cell::new([0; SIM_MEMORY_SIZE]),
+            pointer: Cell::new(0),
+            selected: Cell::new(None),
+            next_error: Cell::new(None),
+            error_after: Cell::new(0),
+        }
+    }
+
+    pub fn set_register(&self, reg: u8, value: u16) {
+        let offset = reg as usize * self.register_width;
+        let mut memory = self.memory.get();
+        if self.register_width == 2 {
+            memory[offset] = (value >> 8) as u8;
+            memory[offset + 1] = value as u8;
+        } else {
+            memory[offset] = value as u8;
+        }
+        self.memory.set(memory);
+    }
+
+    pub fn register(&self, reg: u8) -> u16 {
+        let offset = reg as usize * self.register_width;
+        let memory = self.memory.get();
+        if self.register_width == 2 {
+            (memory[offset] as u16) << 8 | memory[offset + 1] as u16
+        } else {
+            memory[offset] as u16
+        }
+    }
+
+    /// Register the last transfer pointed to
+    pub fn pointer(&self) -> u8 {
+        (self.pointer.get() / self.register_width) as u8
+    }
+
+    /// Makes the next transfer fail with `err`
+    pub fn fail_next(&self, err: I2cError) {
+        self.fail_after(0, err);
+    }
+
+    /// Makes the transfer following the next `transfers` ones fail with `err`
+    pub fn fail_after(&self, transfers: usize, err: I2cError) {
+        self.next_error.set(Some(err));
+        self.error_after.set(transfers);
+    }
+
+    fn begin(&self) -> Result<(), I2cError> {
+        if let Some(err) = self.next_error.get() {
+            let after = self.error_after.get();
+            if after == 0 {
+                self.next_error.set(None);
+                return Err(err);
+            }
+            self.error_after.set(after - 1);
+        }
+
+        if self.selected.get() == Some(self.address) {
+            Ok(())
+        } else {
+            Err(I2cError::AddressNack)
+        }
+    }
+
+    fn set_pointer(&self, reg: u8) {
+        self.pointer.set(reg as usize * self.register_width);
+    }
+
+    fn store(&self, data: &[u8]) {
+        let mut memory = self.memory.get();
+        let mut offset = self.pointer.get();
+        for byte in data {
+            memory[offset % SIM_MEMORY_SIZE] = *byte;
+            offset += 1;
+        }
+        self.memory.set(memory);
+    }
+
+    fn load(&self, data: &mut [u8]) {
+        let memory = self.memory.get();
+        let offset = self.pointer.get();
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = memory[(offset + i) % SIM_MEMORY_SIZE];
+        }
+    }
+}
+
+impl RegisterBus for SimulatedBus {
+    fn select(&self, _interface: I2cInterface, address: u8, _speed: I2cSpeed) {
+        self.selected.set(Some(address));
+    }
+
+    fn deselect(&self) {
+        self.selected.set(None);
+    }
+
+    fn read(&self, buf: &mut [u8], len: u8) -> Result<(), I2cError> {
+        self.begin()?;
+        self.load(&mut buf[..len as usize]);
+        Ok(())
+    }
+
+    fn write(&self, buf: &[u8], len: u8) -> Result<(), I2cError> {
+        if len == 0 {
+            return self.begin();
+        }
+        self.write_prefixed(buf[0], &buf[1..len as usize])
+    }
+
+    fn write_read(&self, buf: &mut [u8], write_len: u8, read_len: u8) -> Result<(), I2cError> {
+        self.write(buf, write_len)?;
+        self.load(&mut buf[..read_len as usize]);
+        Ok(())
+    }
+
+    fn write_prefixed(&self, prefix: u8, data: &[u8]) -> Result<(), I2cError> {
+        self.begin()?;
+        self.set_pointer(prefix);
+        self.store(data);
+        Ok(())
+    }
+
+    fn write_single(&self, data: u8) -> Result<(), I2cError> {
+        self.write_prefixed(data, &[])
+    }
+}
+
+pub struct SimulatedAlarm {
+    now: Cell<u32>,
+    alarm: Cell<Option<u32>>,
+}
+
+impl SimulatedAlarm {
+    pub fn new() -> SimulatedAlarm {
+        SimulatedAlarm {
+            now: Cell::new(0),
+            alarm: Cell::new(None),
+        }
+    }
+
+    /// Advances the time to the alarm and disarms it, returns whether the
+    /// alarm was armed. The client has to be called by the test.
+    pub fn expire(&self) -> bool {
+        match self.alarm.get() {
+            Some(tics) => {
+                self.now.set(tics);
+                self.alarm.set(None);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Time for SimulatedAlarm {
+    type Frequency = Freq65KHz;
+
+    fn disable(&self) {
+        self.alarm.set(None);
+    }
+
+    fn is_armed(&self) -> bool {
+        self.alarm.get().is_some()
+    }
+}
+
+impl Alarm for SimulatedAlarm {
+    fn now(&self) -> u32 {
+        self.now.get()
+    }
+
+    fn set_alarm(&self, tics: u32) {
+        self.alarm.set(Some(tics));
+    }
+
+    fn get_alarm(&self) -> u32 {
+        self.alarm.get().unwrap_or(0)
+    }
+}
+
+pub struct SimulatedPin {
+    level: Cell<bool>,
+    interrupt: Cell<bool>,
+}
+
+impl SimulatedPin {
+    pub fn new() -> SimulatedPin {
+        SimulatedPin {
+            level: Cell::new(false),
+            interrupt: Cell::new(false),
+        }
+    }
+
+    pub fn interrupt_enabled(&self) -> bool {
+        self.interrupt.get()
+    }
+}
+
+impl gpio::Pin for SimulatedPin {
+    fn make_output(&self) {}
+
+    fn make_input(&self) {}
+
+    fn disable(&self) {}
+
+    fn set(&self) {
+        self.level.set(true);
+    }
+
+    fn clear(&self) {
+        self.level.set(false);
+    }
+
+    fn toggle(&self) {
+        self.level.set(!self.level.get());
+    }
+
+    fn read(&self) -> bool {
+        self.level.get()
+    }
+
+    fn enable_interrupt(&self, _identifier: usize, _mode: gpio::InterruptMode) {
+        self.interrupt.set(true);
+    }
+
+    fn disable_interrupt(&self) {
+        self.interrupt.set(false);
+    }
+}
+
+impl gpio::PinCtl for SimulatedPin {
+    fn set_input_mode(&self, _mode: gpio::InputMode) {}
+}
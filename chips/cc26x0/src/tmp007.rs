@@ -0,0 +1,342 @@
+//! TMP007 infrared thermopile temperature sensor
+//!
+//! Measures the temperature of the object in front of the sensor as well as
+//! its own die temperature. The sensor converts continuously at the
+//! configured rate, so a reading simply fetches the latest result.
+//! Temperatures are reported in hundredths of a degree Celsius.
+//!
+//! When alerts are enabled the sensor pulls its ALERT pin low as soon as
+//! the object temperature crosses one of the limits, and the client is
+//! told which limit has been crossed. Limits have a resolution of half a
+//! degree.
+
+use core::cell::Cell;
+use i2c::{I2cError, I2cInterface};
+use kernel::ReturnCode;
+use kernel::hil::gpio;
+use sensor::{self, RegisterBus, Sensor};
+
+pub const TMP007_DIE_TEMP_REG: u8 = 0x01;
+pub const TMP007_CONF_REG: u8 = 0x02;
+pub const TMP007_OBJ_TEMP_REG: u8 = 0x03;
+pub const TMP007_STATUS_REG: u8 = 0x04;
+pub const TMP007_STATUS_MASK_REG: u8 = 0x05;
+pub const TMP007_OBJ_HIGH_LIMIT_REG: u8 = 0x06;
+pub const TMP007_OBJ_LOW_LIMIT_REG: u8 = 0x07;
+pub const TMP007_MANUFACTURER_ID_REG: u8 = 0x1E;
+pub const TMP007_DEVICE_ID_REG: u8 = 0x1F;
+
+pub const TMP007_MANUFACTURER_ID: u16 = 0x5449; // Texas Instruments
+pub const TMP007_DEVICE_ID: u16 = 0x0078;
+
+pub const TMP007_CONFIG_MOD: u16 = 1 << 12; // Continuous conversion
+pub const TMP007_CONFIG_CR_SHIFT: u16 = 9;
+pub const TMP007_CONFIG_ALRT_EN: u16 = 1 << 8; // Drive the ALERT pin
+pub const TMP007_CONFIG_TC: u16 = 1 << 6; // Transient correction
+pub const TMP007_CONFIG_INT_COMP: u16 = 1 << 5; // Latch flags until status is read
+
+// Bits shared by the status and status mask registers
+pub const TMP007_STATUS_ALRT: u16 = 1 << 15;
+pub const TMP007_STATUS_OBJ_HIGH: u16 = 1 << 13;
+pub const TMP007_STATUS_OBJ_LOW: u16 = 1 << 12;
+
+// Limits are stored left aligned with a resolution of 0.5 degrees
+pub const TMP007_LIMIT_SHIFT: u16 = 6;
+pub const TMP007_LIMIT_CENTI_PER_LSB: i32 = 50;
+// Limits are 10 bit two's complement
+pub const TMP007_LIMIT_MIN: i32 = -512 * TMP007_LIMIT_CENTI_PER_LSB;
+pub const TMP007_LIMIT_MAX: i32 = 511 * TMP007_LIMIT_CENTI_PER_LSB;
+
+// Set in the object temperature if the result is not valid
+pub const TMP007_OBJ_TEMP_NDVF: u16 = 0x1;
+
+pub const TMP007_INTERFACE: I2cInterface = I2cInterface::Interface0;
+pub const TMP007_ADDRESS: u8 = 0x44;
+
+/// Number of averaged samples per result, which determines the conversion time
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ConversionRate {
+    /// 0.26s per result
+    Average1 = 0,
+    /// 0.51s per result
+    Average2 = 1,
+    /// 1.01s per result
+    Average4 = 2,
+    /// 2.01s per result
+    Average8 = 3,
+    /// 4.01s per result
+    Average16 = 4,
+}
+
+impl ConversionRate {
+    pub fn from_usize(rate: usize) -> Option<ConversionRate> {
+        match rate {
+            0 => Some(ConversionRate::Average1),
+            1 => Some(ConversionRate::Average2),
+            2 => Some(ConversionRate::Average4),
+            3 => Some(ConversionRate::Average8),
+            4 => Some(ConversionRate::Average16),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Limit {
+    High = 1,
+    Low = 2,
+}
+
+pub trait Tmp007Client {
+    /// Called when the object temperature has crossed a limit, with the
+    /// object temperature in hundredths of a degree
+    fn alert(&self, limit: Limit, temperature: i32);
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Tmp007Error {
+    Bus(I2cError),
+    /// The sensor has not produced a valid result yet
+    InvalidData,
+    /// The limit is outside the range the sensor can represent
+    LimitOutOfRange,
+}
+
+impl From<I2cError> for Tmp007Error {
+    fn from(err: I2cError) -> Tmp007Error {
+        Tmp007Error::Bus(err)
+    }
+}
+
+impl From<Tmp007Error> for ReturnCode {
+    fn from(err: Tmp007Error) -> ReturnCode {
+        match err {
+            Tmp007Error::Bus(err) => err.into(),
+            Tmp007Error::InvalidData => ReturnCode::EBUSY,
+            Tmp007Error::LimitOutOfRange => ReturnCode::EINVAL,
+        }
+    }
+}
+
+pub struct TMP007<'a, B: RegisterBus + 'a, P: gpio::Pin + gpio::PinCtl + 'a> {
+    sensor: Cell<Sensor<'a, B>>,
+    alert_pin: &'a P,
+    rate: Cell<ConversionRate>,
+    alerts_enabled: Cell<bool>,
+    present: Cell<bool>,
+    client: Cell<Option<&'a Tmp007Client>>,
+}
+
+impl<'a, B: RegisterBus + 'a, P: gpio::Pin + gpio::PinCtl + 'a> TMP007<'a, B, P> {
+    pub fn new(bus: &'a B, alert_pin: &'a P) -> TMP007<'a, B, P> {
+        TMP007 {
+            sensor: Cell::new(Sensor::new(bus, TMP007_INTERFACE, TMP007_ADDRESS)),
+            alert_pin,
+            rate: Cell::new(ConversionRate::Average4),
+            alerts_enabled: Cell::new(false),
+            present: Cell::new(false),
+            client: Cell::new(None),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a Tmp007Client) {
+        self.client.set(Some(client));
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.present.get()
+    }
+
+    /// Checks the identification registers and starts continuous conversion
+    pub fn init(&self) -> ReturnCode {
+        let result = self.read_register(TMP007_MANUFACTURER_ID_REG).and_then(|manufacturer| {
+            self.read_register(TMP007_DEVICE_ID_REG).map(|device| {
+                manufacturer == TMP007_MANUFACTURER_ID && device == TMP007_DEVICE_ID
+            })
+        });
+
+        match result {
+            Ok(true) => match self.write_config() {
+                Ok(()) => {
+                    self.present.set(true);
+                    ReturnCode::SUCCESS
+                }
+                Err(err) => err.into(),
+            },
+            Ok(false) => ReturnCode::ENODEVICE,
+            Err(err) => err.into(),
+        }
+    }
+
+    pub fn set_conversion_rate(&self, rate: ConversionRate) -> Result<(), I2cError> {
+        self.rate.set(rate);
+        self.write_config()
+    }
+
+    /// Object temperature in hundredths of a degree Celsius
+    pub fn object_temperature(&self) -> Result<i32, Tmp007Error> {
+        let raw = self.read_register(TMP007_OBJ_TEMP_REG)?;
+        if raw & TMP007_OBJ_TEMP_NDVF != 0 {
+            return Err(Tmp007Error::InvalidData);
+        }
+        Ok(convert_to_celsius(raw))
+    }
+
+    /// Die temperature in hundredths of a degree Celsius
+    pub fn die_temperature(&self) -> Result<i32, Tmp007Error> {
+        let raw = self.read_register(TMP007_DIE_TEMP_REG)?;
+        Ok(convert_to_celsius(raw))
+    }
+
+    pub fn set_high_limit(&self, centi_celsius: i32) -> Result<(), Tmp007Error> {
+        self.set_limit(TMP007_OBJ_HIGH_LIMIT_REG, centi_celsius)
+    }
+
+    pub fn set_low_limit(&self, centi_celsius: i32) -> Result<(), Tmp007Error> {
+        self.set_limit(TMP007_OBJ_LOW_LIMIT_REG, centi_celsius)
+    }
+
+    fn set_limit(&self, reg: u8, centi_celsius: i32) -> Result<(), Tmp007Error> {
+        let limit = convert_to_limit(centi_celsius).ok_or(Tmp007Error::LimitOutOfRange)?;
+        self.write_register(reg, limit)?;
+        Ok(())
+    }
+
+    /// Lets the sensor raise its ALERT pin when the object temperature
+    /// leaves the window set by the limits.
+    pub fn enable_alerts(&self) -> Result<(), I2cError> {
+        self.alerts_enabled.set(true);
+        self.write_config()?;
+        self.write_register(
+            TMP007_STATUS_MASK_REG,
+            TMP007_STATUS_ALRT | TMP007_STATUS_OBJ_HIGH | TMP007_STATUS_OBJ_LOW,
+        )?;
+
+        // Clear any stale flags before listening for the pin
+        self.read_register(TMP007_STATUS_REG)?;
+
+        // ALERT is open drain and active low
+        self.alert_pin.make_input();
+        self.alert_pin.set_input_mode(gpio::InputMode::PullUp);
+        self.alert_pin.enable_interrupt(0, gpio::InterruptMode::FallingEdge);
+        Ok(())
+    }
+
+    pub fn disable_alerts(&self) -> Result<(), I2cError> {
+        self.alert_pin.disable_interrupt();
+        self.alerts_enabled.set(false);
+        self.write_register(TMP007_STATUS_MASK_REG, 0)?;
+        self.write_config()
+    }
+
+    fn config(&self) -> u16 {
+        let mut config = TMP007_CONFIG_MOD | TMP007_CONFIG_TC
+            | ((self.rate.get() as u16) << TMP007_CONFIG_CR_SHIFT);
+        if self.alerts_enabled.get() {
+            config |= TMP007_CONFIG_ALRT_EN | TMP007_CONFIG_INT_COMP;
+        }
+        config
+    }
+
+    fn write_config(&self) -> Result<(), I2cError> {
+        self.write_register(TMP007_CONF_REG, self.config())
+    }
+
+    fn read_register(&self, reg: u8) -> Result<u16, I2cError> {
+        let sensor = self.sensor.get();
+        let mut buf = [0; 2];
+
+        sensor.select();
+        let result = sensor.read_from_reg(reg, &mut buf, 2);
+        sensor.deselect();
+        result?;
+
+        Ok((buf[0] as u16) << 8 | (buf[1] as u16))
+    }
+
+    fn write_register(&self, reg: u8, value: u16) -> Result<(), I2cError> {
+        let sensor = self.sensor.get();
+        let buf = [(value >> 8) as u8, value as u8];
+
+        sensor.select();
+        let result = sensor.write_to_reg(reg, &buf);
+        sensor.deselect();
+        result
+    }
+}
+
+/// Limits are 10 bit two's complement, left aligned, at 0.5 degrees per bit
+/// and are truncated towards zero. Returns `None` if the sensor cannot
+/// represent the limit.
+pub fn convert_to_limit(centi_celsius: i32) -> Option<u16> {
+    if centi_celsius < TMP007_LIMIT_MIN || centi_celsius > TMP007_LIMIT_MAX {
+        return None;
+    }
+
+    let half_degrees = centi_celsius / TMP007_LIMIT_CENTI_PER_LSB;
+    Some(((half_degrees as i16) << TMP007_LIMIT_SHIFT) as u16)
+}
+
+/// Temperatures are 14 bit two's complement, left aligned, at 0.03125 degrees per bit
+pub fn convert_to_celsius(raw: u16) -> i32 {
+    sensor::centi_celsius((raw as i16 >> 2) as i32, 3125, 1000, 0)
+}
+
+impl<'a, B: RegisterBus + 'a, P: gpio::Pin + gpio::PinCtl + 'a> gpio::Client for TMP007<'a, B, P> {
+    fn fired(&self, _: usize) {
+        // Reading the status clears the latched flags and releases the pin
+        let status = match self.read_register(TMP007_STATUS_REG) {
+            Ok(status) => status,
+            Err(_) => return,
+        };
+
+        let limit = if status & TMP007_STATUS_OBJ_HIGH != 0 {
+            Limit::High
+        } else if status & TMP007_STATUS_OBJ_LOW != 0 {
+            Limit::Low
+        } else {
+            return;
+        };
+
+        if let Ok(temp) = self.object_temperature() {
+            self.client.get().map(|client| client.alert(limit, temp));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_are_left_aligned_half_degrees() {
+        assert_eq!(convert_to_limit(0), Some(0));
+        assert_eq!(convert_to_limit(2550), Some(51 << 6));
+        assert_eq!(convert_to_limit(2575), Some(51 << 6));
+        assert_eq!(convert_to_limit(-50), Some(0xFFC0));
+        assert_eq!(convert_to_limit(-1000), Some((-20i16 << 6) as u16));
+    }
+
+    #[test]
+    fn limits_cover_the_representable_range() {
+        assert_eq!(convert_to_limit(TMP007_LIMIT_MAX), Some(0x7FC0));
+        assert_eq!(convert_to_limit(TMP007_LIMIT_MIN), Some(0x8000));
+        assert_eq!(convert_to_limit(TMP007_LIMIT_MAX + 1), None);
+        assert_eq!(convert_to_limit(TMP007_LIMIT_MIN - 1), None);
+        assert_eq!(convert_to_limit(i32::max_value()), None);
+        assert_eq!(convert_to_limit(i32::min_value()), None);
+    }
+
+    #[test]
+    fn out_of_range_limits_are_invalid() {
+        let err: ReturnCode = Tmp007Error::LimitOutOfRange.into();
+        assert_eq!(err, ReturnCode::EINVAL);
+    }
+
+    #[test]
+    fn temperatures_are_sign_extended() {
+        assert_eq!(convert_to_celsius(0x0C80), 2500);
+        assert_eq!(convert_to_celsius(0xFFFC), -3);
+        assert_eq!(convert_to_celsius(0xE700), -5000);
+    }
+}